@@ -1,3 +1,22 @@
 import { PreviewOptions } from "@nocojs/core";
 
-export const preview = (url: string, options?: PreviewOptions): string => url;
\ No newline at end of file
+export interface PreviewMeta {
+  placeholder: string;
+  width: number;
+  height: number;
+  aspectRatio: number;
+  dominantColor: string;
+  format: string;
+}
+
+export const preview = (url: string | URL, options?: PreviewOptions): string => url.toString();
+
+// The metadata is only known at build time, at runtime the placeholder is the original URL
+export const previewMeta = (url: string | URL, options?: PreviewOptions): PreviewMeta => ({
+  placeholder: url.toString(),
+  width: 0,
+  height: 0,
+  aspectRatio: 0,
+  dominantColor: "transparent",
+  format: "",
+});
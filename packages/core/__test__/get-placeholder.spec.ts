@@ -1,7 +1,9 @@
+import { mkdir } from 'fs/promises';
 import path from 'path';
+import sharp from 'sharp';
 import { describe, expect, test } from 'vitest';
 import { getPlaceholder, GetPlaceholderOptions } from '../api';
-import { defaultTransformOptions } from './utils';
+import { base64ToSharpImage, defaultTransformOptions } from './utils';
 
 describe('getPlaceholder function tests', () => {
   const testImagePath = path.join(defaultTransformOptions.publicDir!, 'good_boy_4x5.jpg');
@@ -49,6 +51,129 @@ describe('getPlaceholder function tests', () => {
     });
   });
 
+  describe('Trimming', () => {
+    test('should trim uniform borders and report the trimmed box', async () => {
+      const borderedDir = path.join(defaultTransformOptions.cacheFileDir!, 'trim_images');
+      await mkdir(borderedDir, { recursive: true });
+      const borderedImagePath = path.join(borderedDir, 'bordered.png');
+
+      const { width, height } = await sharp(testImagePath).metadata();
+      await sharp(testImagePath)
+        .extend({ top: 40, bottom: 40, left: 60, right: 60, background: '#ffffff' })
+        .png()
+        .toFile(borderedImagePath);
+
+      const result = await getPlaceholder(borderedImagePath, {
+        ...baseOptions,
+        cache: false,
+        trim: true,
+      });
+
+      expect(result.isError).toBe(false);
+      expect(result.trimBox).toBeDefined();
+      expect(result.trimBox!.x).toBeGreaterThanOrEqual(60);
+      expect(result.trimBox!.y).toBeGreaterThanOrEqual(40);
+      expect(result.trimBox!.width).toBeLessThanOrEqual(width!);
+      expect(result.trimBox!.height).toBeLessThanOrEqual(height!);
+    });
+
+    test('should not report a trimmed box when trimming is disabled', async () => {
+      const result = await getPlaceholder(testImagePath, { ...baseOptions, cache: false });
+      expect(result.trimBox).toBeUndefined();
+    });
+  });
+
+  describe('Side bounds', () => {
+    const getSize = async (options: GetPlaceholderOptions) => {
+      const result = await getPlaceholder(testImagePath, {
+        ...baseOptions,
+        ...options,
+        cache: false,
+        wrapWithSvg: false,
+      });
+      const { width, height } = await base64ToSharpImage(result.placeholder).metadata();
+      return [width, height];
+    };
+
+    test('should scale both sides to fit maxSide', async () => {
+      expect(await getSize({ maxSide: 10 })).toEqual([8, 10]);
+      expect(await getSize({ width: 40, height: 20, maxSide: 10 })).toEqual([10, 5]);
+    });
+
+    test('should scale both sides to fit minSide', async () => {
+      expect(await getSize({ width: 4, minSide: 8 })).toEqual([8, 10]);
+    });
+
+    test('should swap inverted bounds', async () => {
+      expect(await getSize({ minSide: 10, maxSide: 5 })).toEqual([8, 10]);
+    });
+
+    test('should cache placeholders with different bounds separately', async () => {
+      const options: GetPlaceholderOptions = {
+        ...baseOptions,
+        cacheFileDir: path.join(baseOptions.cacheFileDir!, 'side_bounds'),
+        cache: true,
+        wrapWithSvg: false,
+      };
+      const unbounded = await getPlaceholder(testImagePath, options);
+      const bounded = await getPlaceholder(testImagePath, { ...options, maxSide: 10 });
+
+      expect(await base64ToSharpImage(unbounded.placeholder).metadata()).toMatchObject({ width: 16, height: 20 });
+      expect(await base64ToSharpImage(bounded.placeholder).metadata()).toMatchObject({ width: 8, height: 10 });
+    });
+  });
+
+  describe('Byte budget', () => {
+    test('should fit the placeholder in maxBytes and report the chosen parameters', async () => {
+      const result = await getPlaceholder(testImagePath, {
+        ...baseOptions,
+        cache: false,
+        width: 32,
+        maxBytes: 1200,
+      });
+
+      expect(result.isError).toBe(false);
+      expect(result.placeholder.length).toBeLessThanOrEqual(1200);
+      expect(result.tuning).toBeDefined();
+      expect(result.tuning!.bytes).toBe(result.placeholder.length);
+      expect(result.tuning!.width).toBeLessThanOrEqual(32);
+    });
+  });
+
+  describe('Similarity target', () => {
+    test('should report the achieved similarity', async () => {
+      const result = await getPlaceholder(testImagePath, {
+        ...baseOptions,
+        cache: false,
+        targetSimilarity: 0.5,
+      });
+
+      expect(result.isError).toBe(false);
+      expect(result.similarity).toBeDefined();
+      expect(result.similarity!).toBeGreaterThan(0);
+      expect(result.similarity!).toBeLessThanOrEqual(1);
+    });
+
+    test('a higher target should not produce a smaller placeholder', async () => {
+      const low = await getPlaceholder(testImagePath, {
+        ...baseOptions,
+        cache: false,
+        wrapWithSvg: false,
+        targetSimilarity: 0.3,
+      });
+      const high = await getPlaceholder(testImagePath, {
+        ...baseOptions,
+        cache: false,
+        wrapWithSvg: false,
+        targetSimilarity: 0.9,
+      });
+
+      const lowMetadata = await base64ToSharpImage(low.placeholder).metadata();
+      const highMetadata = await base64ToSharpImage(high.placeholder).metadata();
+      expect(highMetadata.width!).toBeGreaterThanOrEqual(lowMetadata.width!);
+    });
+  });
+
   describe('Error handling', () => {
     test('should handle non-existent local file', async () => {
       const nonExistentPath = '/non-existent.jpg';
@@ -1,8 +1,15 @@
-import { readFile } from 'fs/promises';
+import { copyFile, mkdir, readFile, rm } from 'fs/promises';
 import path from 'path';
 import { describe, expect, test } from 'vitest';
-import { transform } from '../api';
-import { checkPreviewImage, defaultTransformOptions, getCacheFileDirName, getInput } from './utils';
+import { transform, transformCss, transformHtml, transformMarkdown } from '../api';
+import {
+  base64ToSharpImage,
+  checkPreviewImage,
+  decodeMappings,
+  defaultTransformOptions,
+  getCacheFileDirName,
+  getInput,
+} from './utils';
 
 describe('Basic Transform Tests', () => {
   test('transforms with no options', async () => {
@@ -131,3 +138,1165 @@ describe('Global placeholderType option tests with remote image', () => {
     expect(checkPreviewImage(result.code)).toBeTruthy();
   });
 });
+
+describe('preserveFormatting option', () => {
+  test('keeps comments and formatting around replaced calls', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+// hero image
+const img   =   preview('/good_boy_4x5.jpg');
+export default img;
+`;
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      preserveFormatting: true,
+    });
+
+    expect(result.code.startsWith(`\n// hero image\nconst img   =   `)).toBe(true);
+    expect(result.code.endsWith(`;\nexport default img;\n`)).toBe(true);
+    expect(checkPreviewImage(result.code)).toBeTruthy();
+    expect(result.map).not.toBeNull();
+  });
+
+  test('returns files without matched calls byte-identical', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+/* nothing to replace */
+const   value = "unchanged"  ;
+`;
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      preserveFormatting: true,
+    });
+
+    expect(result.code).toBe(input);
+  });
+});
+
+describe('inputSourcemap option', () => {
+  // Maps every line of the transform input to the same line of `original.tsx`.
+  const inputSourcemap = JSON.stringify({
+    version: 3,
+    sources: ['original.tsx'],
+    sourcesContent: ['original source'],
+    names: [],
+    mappings: 'AAAA;AACA;AACA',
+  });
+
+  test('composes the output sourcemap with the input sourcemap', async () => {
+    const input = getInput();
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      inputSourcemap,
+    });
+
+    expect(checkPreviewImage(result.code)).toBeTruthy();
+    const map = JSON.parse(result.map!);
+    expect(map.sources).toEqual(['original.tsx']);
+    expect(map.sourcesContent).toEqual(['original source']);
+  });
+
+  test('composes patched output with the input sourcemap', async () => {
+    const input = getInput();
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      preserveFormatting: true,
+      inputSourcemap,
+    });
+
+    const map = JSON.parse(result.map!);
+    expect(map.sources).toEqual(['original.tsx']);
+  });
+
+  test('maps indented lines and lines after a replaced call', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+function load() {
+  const img = preview('/good_boy_4x5.jpg');
+  return img;
+}`;
+    // The indented lines start mapping at column 2, eg: `return img;` maps to line 11, column 4
+    const indentedSourcemap = JSON.stringify({
+      version: 3,
+      sources: ['original.tsx'],
+      sourcesContent: ['original source'],
+      names: [],
+      mappings: 'AAAA;;AAEA;EAQI;EACA;AACJ',
+    });
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      preserveFormatting: true,
+      inputSourcemap: indentedSourcemap,
+    });
+
+    const lines = result.code.split('\n');
+    const segments = decodeMappings(JSON.parse(result.map!).mappings);
+    const callLine = lines.findIndex((line) => line.includes('const img = "data:image/'));
+    const nextLine = lines.findIndex((line) => line.includes('return img;'));
+
+    expect(segments[callLine]).toContainEqual([2, 0, 10, 4]);
+    expect(segments[nextLine]).toContainEqual([2, 0, 11, 4]);
+  });
+});
+
+describe('Import forms', () => {
+  const url = '/good_boy_4x5.jpg';
+
+  test('namespace import', async () => {
+    const input = `import * as noco from '@nocojs/client';
+
+const img = noco.preview("${url}");`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+    expect(checkPreviewImage(result.code)).toBeTruthy();
+  });
+
+  test('CommonJS require destructuring', async () => {
+    const input = `const { preview: previewFn } = require('@nocojs/client');
+
+const img = previewFn("${url}");`;
+    const result = await transform(input, 'index.cjs', defaultTransformOptions);
+    expect(checkPreviewImage(result.code)).toBeTruthy();
+  });
+
+  test('CommonJS require binding', async () => {
+    const input = `const noco = require('@nocojs/client');
+
+const img = noco.preview("${url}");`;
+    const result = await transform(input, 'index.cjs', defaultTransformOptions);
+    expect(checkPreviewImage(result.code)).toBeTruthy();
+  });
+
+  test('dynamic import destructuring', async () => {
+    const input = `const { preview } = await import('@nocojs/client');
+
+const img = preview("${url}");`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+    expect(checkPreviewImage(result.code)).toBeTruthy();
+  });
+
+  test('ignores member calls on unrelated objects', async () => {
+    const input = `import * as noco from '@nocojs/client';
+const other = { preview: (url: string) => url };
+
+const img = other.preview("${url}");`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+    expect(checkPreviewImage(result.code)).toBe(false);
+  });
+});
+
+describe('importSources option', () => {
+  const url = '/good_boy_4x5.jpg';
+  const importSources = [
+    {
+      source: '@acme/design-system',
+      names: {
+        blurPreview: { placeholderType: 'blurred' as const },
+        colorPreview: { placeholderType: 'dominant-color' as const },
+      },
+    },
+  ];
+
+  test('transforms functions from a custom import source', async () => {
+    const input = `import { blurPreview } from '@acme/design-system';
+
+const img = blurPreview("${url}");`;
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      importSources,
+    });
+    expect(checkPreviewImage(result.code)).toBeTruthy();
+  });
+
+  test('applies the preset of the called function', async () => {
+    const input = `import * as ds from '@acme/design-system';
+
+const img = ds.colorPreview("${url}");`;
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      importSources,
+      wrapWithSvg: false,
+    });
+    const image = await base64ToSharpImage(result.code.match(/const img\s*=\s*"(.*?)";/)![1]);
+    const stats = await image.stats();
+    expect(stats.isOpaque).toBe(true);
+    // A dominant color placeholder only has a single color
+    expect(stats.channels.every((channel) => channel.min === channel.max)).toBe(true);
+  });
+
+  test('ignores names that are not listed', async () => {
+    const input = `import { preview } from '@acme/design-system';
+
+const img = preview("${url}");`;
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      importSources,
+    });
+    expect(checkPreviewImage(result.code)).toBe(false);
+  });
+});
+
+describe('Constant folding', () => {
+  test('resolves const bindings, concatenation and templates', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const BASE = '/good_boy';
+const EXT = '.jpg';
+const img0 = preview(BASE + '_4x5' + EXT);
+const img1 = preview(\`\${BASE}_4x5\${EXT}\`);`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    // Both calls resolve to the same image, so the placeholder is hoisted
+    expect(result.code).toMatch(/const __noco_0 = "data:image\//);
+    expect(result.code).toContain('const img0 = __noco_0;');
+    expect(result.code).toContain('const img1 = __noco_0;');
+  });
+
+  test('resolves spreads of const option objects', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const CARD_OPTS = { placeholderType: 'normal', width: 8 } as const;
+const img = preview('/good_boy_4x5.jpg', { ...CARD_OPTS, width: 24, wrapWithSvg: false });`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    const image = base64ToSharpImage(result.code.match(/const img\s*=\s*"(.*?)";/)![1]);
+    const metadata = await image.metadata();
+    expect(metadata.width).toBe(24);
+  });
+
+  test('reports arguments that cannot be resolved', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+let path = '/good_boy_4x5.jpg';
+const img = preview(path);`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(checkPreviewImage(result.code)).toBe(false);
+    const log = result.logs.find((log) => log.message.includes('Could not statically resolve'));
+    expect(log).toBeDefined();
+    // Reported at the line and column of the argument
+    expect(log!.message).toMatch(/index\.ts:4:21\b/);
+  });
+});
+
+describe('define option', () => {
+  const define = {
+    'process.env.IMAGE_BASE': '/good_boy',
+    'import.meta.env.IMAGE_EXT': '.jpg',
+  };
+
+  test('substitutes defined member expressions', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img0 = preview(process.env.IMAGE_BASE + '_4x5' + import.meta.env.IMAGE_EXT);
+const img1 = preview(\`\${process.env["IMAGE_BASE"]}_4x5\${import.meta.env.IMAGE_EXT}\`);`;
+    const result = await transform(input, 'index.ts', { ...defaultTransformOptions, define });
+
+    // Both calls resolve to the same image, so the placeholder is hoisted
+    expect(result.code).toMatch(/const __noco_0 = "data:image\//);
+    expect(result.code).toContain('const img0 = __noco_0;');
+    expect(result.code).toContain('const img1 = __noco_0;');
+  });
+
+  test('does not substitute shadowed bindings', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+function load(process: { env: Record<string, string> }) {
+  const img = preview(process.env.IMAGE_BASE + '_4x5.jpg');
+  return img;
+}`;
+    const result = await transform(input, 'index.ts', { ...defaultTransformOptions, define });
+
+    expect(result.code).toContain('preview(');
+  });
+});
+
+describe('Conditional arguments', () => {
+  test('rewrites a conditional into a conditional of placeholders', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const isSmall = window.innerWidth < 600;
+const img = preview(isSmall ? '/good_boy_4x5.jpg' : '/good_boy.webp');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    const branches = result.code.match(/isSmall \? "(data:image.*?)" : "(data:image.*?)"/);
+    expect(branches).not.toBeNull();
+  });
+
+  test('keeps testing the unresolvable operand of ||', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview(window.heroImage || '/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toMatch(/window\.heroImage \? preview\(window\.heroImage\) : "data:image/);
+  });
+
+  test('keeps testing the unresolvable operand of ?? for null', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview(window.heroImage ?? '/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toMatch(/window\.heroImage != null \? preview\(window\.heroImage\) : "data:image/);
+  });
+
+  test('keeps calls whose left operand has side effects', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview(getHeroImage() || '/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toMatch(/preview\(getHeroImage\(\) \|\| "\/good_boy_4x5.jpg"\)/);
+    expect(result.code.match(/getHeroImage\(\)/g)).toHaveLength(1);
+  });
+
+  test('does not treat the operands of && as images', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview(window.showHero && '/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toMatch(/preview\(window\.showHero && "\/good_boy_4x5.jpg"\)/);
+    expect(result.code).not.toContain('data:image');
+  });
+});
+
+describe('Mapped const arrays', () => {
+  test('precomputes a map over an array of urls', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const GALLERY = ['/good_boy_4x5.jpg', '/good_boy.webp'];
+const images = GALLERY.map((src) => preview(src));`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).not.toContain('.map(');
+    expect(result.code).toMatch(/const images = \["data:image.*?", "data:image.*?"\]/);
+  });
+
+  test('precomputes a map that spreads the items', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const GALLERY = [
+  { src: '/good_boy_4x5.jpg', alt: 'Good boy' },
+  { src: '/good_boy.webp', alt: 'Good boy again' },
+];
+const images = GALLERY.map(function (p, index) {
+  return { ...p, index, ph: preview(p.src, { width: 8 }) };
+});`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).not.toContain('.map(');
+    expect(result.code).toContain('alt: "Good boy again"');
+    expect(result.code).toContain('index: 1');
+    expect(result.code.match(/ph: "data:image/g)).toHaveLength(2);
+  });
+});
+
+describe('new URL(..., import.meta.url) arguments', () => {
+  test('resolves the image relative to the transformed file', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview(new URL('./public/good_boy_4x5.jpg', import.meta.url));`;
+    const result = await transform(input, path.join(import.meta.dirname, 'index.ts'), defaultTransformOptions);
+
+    expect(result.code).toMatch(
+      /const img = \(new URL\("\.\/public\/good_boy_4x5\.jpg", import\.meta\.url\), "data:image\/[^"]*"\);/,
+    );
+  });
+
+  test('keeps the URL expression when preserving formatting', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview(new URL('./public/good_boy_4x5.jpg', import.meta.url));`;
+    const result = await transform(input, path.join(import.meta.dirname, 'index.ts'), {
+      ...defaultTransformOptions,
+      preserveFormatting: true,
+    });
+
+    expect(result.code).toMatch(
+      /const img = \(new URL\("\.\/public\/good_boy_4x5\.jpg", import\.meta\.url\), "data:image\/[^"]*"\);/,
+    );
+  });
+
+  test('ignores a local URL binding', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+class URL {}
+const img = preview(new URL('./public/good_boy_4x5.jpg', import.meta.url));`;
+    const result = await transform(input, path.join(import.meta.dirname, 'index.ts'), defaultTransformOptions);
+
+    expect(checkPreviewImage(result.code)).toBe(false);
+  });
+});
+
+describe('Local image resolution', () => {
+  const filePath = path.join(import.meta.dirname, 'components', 'Card.tsx');
+
+  test('resolves paths relative to the transformed file', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview('../public/good_boy_4x5.jpg');`;
+    const result = await transform(input, filePath, defaultTransformOptions);
+
+    expect(checkPreviewImage(result.code)).toBe(true);
+  });
+
+  test('resolves aliased paths', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview('@/images/good_boy_4x5.jpg');`;
+    const result = await transform(input, filePath, {
+      ...defaultTransformOptions,
+      alias: { '@': import.meta.dirname, '@/images': path.join(import.meta.dirname, 'static') },
+    });
+
+    expect(checkPreviewImage(result.code)).toBe(true);
+  });
+
+  test('searches every public directory', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview('/good_boy_4x5.jpg');`;
+    const result = await transform(input, filePath, {
+      ...defaultTransformOptions,
+      publicDir: path.join(import.meta.dirname, 'missing'),
+      publicDirs: [path.join(import.meta.dirname, 'static')],
+    });
+
+    expect(checkPreviewImage(result.code)).toBe(true);
+  });
+
+  test('resolves package paths in node_modules', async () => {
+    const packageDir = path.join(import.meta.dirname, 'node_modules', '@acme', 'brand');
+    await mkdir(packageDir, { recursive: true });
+    await copyFile(path.join(import.meta.dirname, 'public', 'good_boy_4x5.jpg'), path.join(packageDir, 'logo.jpg'));
+
+    try {
+      const input = `import { preview } from '@nocojs/client';
+
+const img = preview('@acme/brand/logo.jpg');`;
+      const result = await transform(input, filePath, defaultTransformOptions);
+
+      expect(checkPreviewImage(result.code)).toBe(true);
+    } finally {
+      await rm(path.join(import.meta.dirname, 'node_modules', '@acme'), { recursive: true, force: true });
+    }
+  });
+
+  test('caches local images under their path in the project', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img = preview('../public/good_boy_4x5.jpg');`;
+    const options = { ...defaultTransformOptions, cacheFileDir: getCacheFileDirName() };
+    await transform(input, filePath, options);
+    const result = await transform(input, filePath, options);
+
+    const cacheHits = result.logs.filter((log) => log.message.includes('Cache hit'));
+    expect(cacheHits).toHaveLength(1);
+    expect(cacheHits[0].message).toMatch(/Cache hit for \.\/(\S+\/)?__test__\/public\/good_boy_4x5\.jpg/);
+  });
+});
+
+describe('previewMeta', () => {
+  test('replaces the call with an object of image metadata', async () => {
+    const input = `import { previewMeta } from '@nocojs/client';
+
+const meta = previewMeta('/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).not.toContain('previewMeta(');
+    expect(result.code).toMatch(/placeholder: "data:image/);
+    expect(result.code).toMatch(/width: \d+,\s*height: \d+,\s*aspectRatio: 0\.8/);
+    expect(result.code).toMatch(/dominantColor: "#[0-9a-f]{6}"/);
+    expect(result.code).toContain('format: "jpeg"');
+  });
+
+  test('swaps width and height of rotated images', async () => {
+    const input = `import { previewMeta } from '@nocojs/client';
+
+const meta = previewMeta('/good_boy_4x5.jpg', { rotate: 90 });`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toContain('aspectRatio: 1.25');
+  });
+
+  test('always replaces the whole call', async () => {
+    const input = `import { previewMeta } from '@nocojs/client';
+
+const meta = previewMeta('/good_boy.webp', { replaceFunctionCall: false });`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).not.toContain('previewMeta(');
+    expect(result.code).toContain('format: "webp"');
+  });
+
+  test('processes the metadata of images cached without it', async () => {
+    const options = { ...defaultTransformOptions, cacheFileDir: getCacheFileDirName() };
+    await transform(
+      `import { preview } from '@nocojs/client';
+
+const img = preview('/good_boy_4x5.jpg');`,
+      'index.ts',
+      options,
+    );
+    const result = await transform(
+      `import { previewMeta } from '@nocojs/client';
+
+const meta = previewMeta('/good_boy_4x5.jpg');`,
+      'index.ts',
+      options,
+    );
+
+    expect(result.logs.some((log) => log.message.includes('Cache hit'))).toBe(false);
+    expect(result.code).toMatch(/dominantColor: "#[0-9a-f]{6}"/);
+  });
+});
+
+describe('JSX image enrichment', () => {
+  test('enriches marked img elements', async () => {
+    const input = `export const Hero = () => <img src="/good_boy_4x5.jpg" alt="Good boy" data-nocojs />;`;
+    const result = await transform(input, 'index.tsx', { ...defaultTransformOptions, jsxImages: {} });
+
+    expect(result.code).not.toContain('data-nocojs');
+    expect(result.code).toMatch(/width=\{\d+\}/);
+    expect(result.code).toMatch(/height=\{\d+\}/);
+    expect(result.code).toMatch(/data-placeholder=\{"data:image/);
+    expect(result.code).toMatch(/aspectRatio: "\d+ \/ \d+"/);
+  });
+
+  test('keeps existing dimensions and styles', async () => {
+    const input = `export const Hero = () => <img src="/good_boy_4x5.jpg" width={200} style={{ aspectRatio: "1" }} data-nocojs />;`;
+    const result = await transform(input, 'index.tsx', { ...defaultTransformOptions, jsxImages: {} });
+
+    expect(result.code).toContain('width={200}');
+    expect(result.code).toMatch(/height=\{\d+\}/);
+    expect(result.code).toMatch(/style=\{\{\s*aspectRatio: "1"\s*\}\}/);
+    expect(result.code.match(/aspectRatio/g)).toHaveLength(1);
+  });
+
+  test('adds the attributes inside the element when preserving formatting', async () => {
+    const input = `export const Hero = () => (
+  // The hero image
+  <img src="/good_boy_4x5.jpg" alt="Good boy" data-nocojs />
+);`;
+    const result = await transform(input, 'index.tsx', {
+      ...defaultTransformOptions,
+      jsxImages: {},
+      preserveFormatting: true,
+    });
+
+    expect(result.code).toContain('// The hero image');
+    expect(result.code).toMatch(
+      /<img src="\/good_boy_4x5.jpg" alt="Good boy" width=\{\d+\} height=\{\d+\} data-placeholder=\{"data:image\/[^"]*"\} style=\{\{\s*aspectRatio: "\d+ \/ \d+"\s*\}\} \/>/,
+    );
+  });
+
+  test('enriches configured components with a background placeholder', async () => {
+    const input = `import { Image } from './image';
+
+export const Hero = () => <Image src="/good_boy.webp" />;
+export const Other = () => <img src="/good_boy.webp" />;`;
+    const result = await transform(input, 'index.tsx', {
+      ...defaultTransformOptions,
+      jsxImages: { components: ['Image'], placeholderAttribute: 'style' },
+    });
+
+    expect(result.code).toMatch(/<Image src="\/good_boy.webp" width=\{\d+\}/);
+    expect(result.code).toMatch(/backgroundImage: "url\(\\"data:image/);
+    expect(result.code).toContain('<img src="/good_boy.webp" />');
+  });
+
+  test('does nothing unless enabled', async () => {
+    const input = `export const Hero = () => <img src="/good_boy_4x5.jpg" data-nocojs />;`;
+    const result = await transform(input, 'index.tsx', defaultTransformOptions);
+
+    expect(result.code).toBe(input);
+  });
+});
+
+describe('next/image blurDataURL', () => {
+  test('injects blurDataURL and dimensions', async () => {
+    const input = `import Image from 'next/image';
+
+export const Hero = () => <Image src="/good_boy_4x5.jpg" alt="Good boy" placeholder="blur" />;`;
+    const result = await transform(input, 'page.tsx', { ...defaultTransformOptions, nextImageBlur: true });
+
+    expect(result.code).toMatch(/blurDataURL=\{"data:image\/png;base64,/);
+    expect(result.code).toMatch(/width=\{\d+\}/);
+    expect(result.code).toMatch(/height=\{\d+\}/);
+  });
+
+  test('does not add dimensions to fill images', async () => {
+    const input = `import NextImage from 'next/image';
+
+export const Hero = () => <NextImage src="/good_boy.webp" alt="" fill placeholder="blur" />;`;
+    const result = await transform(input, 'page.tsx', { ...defaultTransformOptions, nextImageBlur: true });
+
+    expect(result.code).toContain('blurDataURL=');
+    expect(result.code).not.toMatch(/width=/);
+  });
+
+  test('skips static imports and elements without a blur placeholder', async () => {
+    const input = `import Image from 'next/image';
+import hero from './hero.jpg';
+
+export const A = () => <Image src={hero} alt="" placeholder="blur" />;
+export const B = () => <Image src="/good_boy.webp" alt="" width={10} height={10} />;`;
+    const result = await transform(input, 'page.tsx', { ...defaultTransformOptions, nextImageBlur: true });
+
+    expect(result.code).not.toContain('blurDataURL');
+  });
+
+  test('is disabled unless set', async () => {
+    const input = `import Image from 'next/image';
+
+export const Hero = () => <Image src="/good_boy_4x5.jpg" alt="Good boy" placeholder="blur" />;`;
+    const result = await transform(input, 'page.tsx', defaultTransformOptions);
+
+    expect(result.code).not.toContain('blurDataURL');
+  });
+
+  test('adds the attributes inside the element when preserving formatting', async () => {
+    const input = `import Image from 'next/image';
+
+// The hero image
+export const Hero = () => <Image src="/good_boy_4x5.jpg" alt="Good boy" placeholder="blur" />;`;
+    const result = await transform(input, 'page.tsx', {
+      ...defaultTransformOptions,
+      nextImageBlur: true,
+      preserveFormatting: true,
+    });
+
+    expect(result.code).toContain('// The hero image');
+    expect(result.code).toMatch(
+      /<Image src="\/good_boy_4x5.jpg" alt="Good boy" placeholder="blur" blurDataURL=\{"data:image\/png;base64,[^"]*"\} width=\{\d+\} height=\{\d+\} \/>;/,
+    );
+  });
+});
+
+describe('Client import removal', () => {
+  test('removes the import once every call is replaced', async () => {
+    const input = getInput();
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(checkPreviewImage(result.code)).toBe(true);
+    expect(result.code).not.toContain('@nocojs/client');
+  });
+
+  test('keeps the import when a call is left unresolved', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img0 = preview('/good_boy_4x5.jpg');
+const img1 = preview(window.heroImage);`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toContain(`import { preview } from "@nocojs/client";`);
+  });
+
+  test('removes only the unused specifiers', async () => {
+    const input = `import { preview, previewMeta, type PreviewMeta } from '@nocojs/client';
+
+const img = preview('/good_boy_4x5.jpg');
+const meta: PreviewMeta = previewMeta(window.heroImage);
+`;
+    const result = await transform(input, 'index.ts', { ...defaultTransformOptions, preserveFormatting: true });
+
+    expect(result.code.startsWith(`import { previewMeta, type PreviewMeta } from '@nocojs/client';\n`)).toBe(true);
+  });
+});
+
+describe('Comment directives', () => {
+  test('skips a call after an inline ignore comment', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img0 = preview('/good_boy_4x5.jpg');
+const img1 = /* @nocojs-ignore */ preview('/good_boy.webp');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(checkPreviewImage(result.code)).toBe(true);
+    expect(result.code).toContain(`preview("/good_boy.webp")`);
+    expect(result.code).toContain(`import { preview } from "@nocojs/client";`);
+  });
+
+  test('skips every call in a statement after an ignore comment', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+// @nocojs-ignore
+const images = [preview('/good_boy_4x5.jpg'), preview('/good_boy.webp')];`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toContain(`preview("/good_boy_4x5.jpg")`);
+    expect(result.code).toContain(`preview("/good_boy.webp")`);
+  });
+
+  test('does not apply trailing ignore comments', async () => {
+    const input = `const img0 = preview('/good_boy_4x5.jpg');
+const img1 = preview('/good_boy.webp'); // @nocojs-ignore
+
+import { preview } from '@nocojs/client';`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).not.toContain('preview(');
+  });
+
+  test('applies the file pragma options', async () => {
+    const input = `// @nocojs replaceFunctionCall=false placeholderType=grayscale
+import { preview } from '@nocojs/client';
+
+const img = preview('/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toMatch(/preview\("data:image\//);
+  });
+
+  test('ignores pragmas after the file header', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+// @nocojs replaceFunctionCall=false
+const img = preview('/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(checkPreviewImage(result.code)).toBe(true);
+  });
+
+  test('still transforms the file with an invalid pragma', async () => {
+    const input = `// @nocojs width=large
+import { preview } from '@nocojs/client';
+
+const img = preview('/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(checkPreviewImage(result.code)).toBe(true);
+  });
+});
+
+describe('Placeholder hoisting', () => {
+  test('declares a repeated placeholder once', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const FALLBACK = '/good_boy_4x5.jpg';
+const cards = [preview(FALLBACK), preview('/good_boy.webp'), preview(FALLBACK)];
+const hero = preview(FALLBACK);`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code.match(/data:image\//g)).toHaveLength(2);
+    expect(result.code).toMatch(/const __noco_0 = "data:image\//);
+    expect(result.code).toMatch(/const cards = \[\s*__noco_0,\s*"data:image\/.*",\s*__noco_0\s*\]/);
+    expect(result.code).toContain('const hero = __noco_0;');
+    expect(result.code).not.toContain('@nocojs/client');
+  });
+
+  test('keeps placeholders with different options apart', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const img0 = preview('/good_boy_4x5.jpg');
+const img1 = preview('/good_boy_4x5.jpg', { width: 8 });`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).not.toContain('__noco_');
+    expect(checkPreviewImage(result.code)).toBe(true);
+  });
+
+  test('skips names used by the file', async () => {
+    const input = `import { preview } from '@nocojs/client';
+
+const __noco_0 = 'taken';
+const img0 = preview('/good_boy_4x5.jpg');
+const img1 = preview('/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'index.ts', defaultTransformOptions);
+
+    expect(result.code).toMatch(/const __noco_1 = "data:image\//);
+    expect(result.code).toContain('const img1 = __noco_1;');
+  });
+
+  test('declares the placeholders after the imports when preserving formatting', async () => {
+    const input = `import { preview } from '@nocojs/client';
+import { render } from './render';
+
+// gallery
+const img0   =   preview('/good_boy_4x5.jpg');
+const img1   =   preview('/good_boy_4x5.jpg');
+render(img0, img1);
+`;
+    const result = await transform(input, 'index.ts', { ...defaultTransformOptions, preserveFormatting: true });
+
+    expect(result.code).toMatch(
+      /^import \{ render \} from '\.\/render';\nconst __noco_0 = "data:image\/[^\n]*";\n\n\/\/ gallery\nconst img0   =   __noco_0;\nconst img1   =   __noco_0;\n/,
+    );
+  });
+});
+
+describe('Component files', () => {
+  test('transforms the script and template bindings of a Vue component', async () => {
+    const input = `<script setup lang="ts">
+import { preview } from '@nocojs/client';
+
+const hero = preview('/good_boy_4x5.jpg');
+</script>
+
+<template>
+  <img :src="preview('/good_boy.webp')" alt="" />
+  <p>{{ preview('/good_boy.webp') }}</p>
+  <img :src="hero" />
+</template>
+`;
+    const result = await transform(input, 'App.vue', defaultTransformOptions);
+
+    expect(result.code).toMatch(/const hero = 'data:image\//);
+    expect(result.code).toMatch(/<img :src="'data:image\/[^"']*'" alt="" \/>/);
+    expect(result.code).toMatch(/<p>\{\{ 'data:image\/[^']*' \}\}<\/p>/);
+    expect(result.code).toContain('<img :src="hero" />');
+    expect(result.code).not.toContain('@nocojs/client');
+  });
+
+  test('escapes quotes in single quoted Vue attributes', async () => {
+    const input = `<script setup>
+import { preview } from '@nocojs/client';
+</script>
+
+<template>
+  <img :src='preview("/good_boy.webp")' />
+</template>
+`;
+    const result = await transform(input, 'App.vue', defaultTransformOptions);
+
+    expect(result.code).toMatch(/<img :src='&#39;data:image\/[^']*&#39;' \/>/);
+  });
+
+  test('transforms the script and markup expressions of a Svelte component', async () => {
+    const input = `<script lang="ts">
+  import { preview } from '@nocojs/client';
+  let { images } = $props();
+</script>
+
+{#each images as image}
+  <img src={preview('/good_boy_4x5.jpg')} alt={image.alt} />
+{/each}
+
+<style>
+  img { color: red; }
+</style>
+`;
+    const result = await transform(input, 'App.svelte', defaultTransformOptions);
+
+    expect(result.code).toMatch(/<img src=\{'data:image\/[^']*'\} alt=\{image.alt\} \/>/);
+    expect(result.code).toContain('{#each images as image}');
+    expect(result.code).toContain('img { color: red; }');
+  });
+
+  test('transforms the frontmatter and markup expressions of an Astro component', async () => {
+    const input = `---
+import { preview } from '@nocojs/client';
+const images = ['/good_boy.webp'];
+---
+
+<div>
+  {images.map((src) => <img src={src} data-preview={preview('/good_boy_4x5.jpg')} />)}
+</div>
+`;
+    const result = await transform(input, 'Page.astro', defaultTransformOptions);
+
+    expect(result.code).toMatch(/data-preview=\{'data:image\/[^']*'\}/);
+    expect(result.code.startsWith('---\nconst images')).toBe(true);
+  });
+
+  test('maps the output to the component', async () => {
+    const input = `<script setup>
+import { preview } from '@nocojs/client';
+</script>
+
+<template>
+  <img :src="preview('/good_boy.webp')" />
+</template>
+`;
+    const result = await transform(input, 'App.vue', defaultTransformOptions);
+
+    const map = JSON.parse(result.map!);
+    expect(map.sources).toEqual(['App.vue']);
+    expect(map.sourcesContent).toEqual([input]);
+    const imgLine = result.code.split('\n').findIndex((line) => line.includes('<img'));
+    expect(map.mappings.split(';')[imgLine]).not.toBe('');
+  });
+
+  test('transforms components with non-ASCII markup', async () => {
+    const vue = `<script setup>
+import { preview } from '@nocojs/client';
+</script>
+
+<template>
+  <p>Café © 日本語</p>
+  <img :src="preview('/good_boy.webp')" alt="Chien élégant" />
+</template>
+`;
+    const svelte = `<script>
+  import { preview } from '@nocojs/client';
+</script>
+
+<h1>Héllo ✓ 日本</h1>
+<img src={preview('/good_boy_4x5.jpg')} alt="é" />
+`;
+    const vueResult = await transform(vue, 'App.vue', defaultTransformOptions);
+    const svelteResult = await transform(svelte, 'App.svelte', defaultTransformOptions);
+
+    expect(vueResult.code).toContain('<p>Café © 日本語</p>');
+    expect(vueResult.code).toMatch(/<img :src="'data:image\/[^"']*'" alt="Chien élégant" \/>/);
+    expect(svelteResult.code).toContain('<h1>Héllo ✓ 日本</h1>');
+    expect(svelteResult.code).toMatch(/<img src=\{'data:image\/[^']*'\} alt="é" \/>/);
+  });
+
+  test('transforms components compiled by an earlier plugin as scripts', async () => {
+    const input = `import { preview } from '@nocojs/client';
+const img = preview('/good_boy_4x5.jpg');`;
+    const result = await transform(input, 'App.vue', defaultTransformOptions);
+
+    expect(checkPreviewImage(result.code)).toBe(true);
+  });
+});
+
+describe('HTML documents', () => {
+  test('swaps the placeholder into marked images', async () => {
+    const input = '<p><img src="/good_boy_4x5.jpg" alt="Good boy" data-nocojs></p>';
+    const result = await transformHtml(input, 'index.html', defaultTransformOptions);
+
+    expect(result.code).toMatch(/<img src="data:image\/[^"]*"/);
+    expect(result.code).toContain('data-src="/good_boy_4x5.jpg"');
+    expect(result.code).toMatch(/width="\d+" height="\d+"/);
+    expect(result.code).toContain('alt="Good boy"');
+    expect(result.code).not.toContain('data-nocojs');
+  });
+
+  test('moves the srcset of marked sources', async () => {
+    const input = `<picture>
+  <source srcset="/good_boy.webp 1x, /good_boy.avif 2x" type="image/webp" data-nocojs>
+  <img src="/good_boy.jpg">
+</picture>`;
+    const result = await transformHtml(input, 'index.html', defaultTransformOptions);
+
+    expect(result.code).toMatch(/<source srcset="data:image\/[^"]*"/);
+    expect(result.code).toContain('data-srcset="/good_boy.webp 1x, /good_boy.avif 2x"');
+    expect(result.code).toContain('<img src="/good_boy.jpg">');
+  });
+
+  test('uses the placeholder as a background image in style mode', async () => {
+    const input = '<img src="/good_boy.webp" style="object-fit: cover;" data-nocojs>';
+    const result = await transformHtml(input, 'index.html', {
+      ...defaultTransformOptions,
+      htmlImages: { placeholderAttribute: 'style' },
+    });
+
+    expect(result.code).not.toMatch(/ src=/);
+    expect(result.code).toContain('data-src="/good_boy.webp"');
+    expect(result.code).toMatch(/style="object-fit: cover; background-image: url\(&quot;data:image\/[^&]*&quot;\); background-size: cover"/);
+  });
+
+  test('keeps replacing the srcset of sources in style mode', async () => {
+    const input = `<picture>
+  <source srcset="/good_boy.webp" type="image/webp" data-nocojs>
+  <img src="/good_boy.jpg" data-nocojs>
+</picture>`;
+    const result = await transformHtml(input, 'index.html', {
+      ...defaultTransformOptions,
+      htmlImages: { placeholderAttribute: 'style' },
+    });
+
+    expect(result.code).toMatch(/<source srcset="data:image\/[^"]*"/);
+    expect(result.code).toContain('data-srcset="/good_boy.webp"');
+    expect(result.code).toContain('data-src="/good_boy.jpg"');
+    expect(result.code).toMatch(/<img [^>]*style="background-image: /);
+  });
+
+  test('rejects unsupported placeholder attributes', async () => {
+    const input = '<img src="/good_boy.webp" data-nocojs>';
+    const result = await transformHtml(input, 'index.html', {
+      ...defaultTransformOptions,
+      // @ts-expect-error Only "src" and "style" are supported
+      htmlImages: { placeholderAttribute: 'styles' },
+    });
+
+    expect(result.code).toBe(input);
+    expect(result.logs.some((log) => log.message.includes('Unsupported HTML placeholder attribute "styles"'))).toBe(true);
+  });
+
+  test('keeps existing dimensions and unmarked images', async () => {
+    const input = `<img src="/good_boy.webp" width="200" data-nocojs>
+<img src="/good_boy_4x5.jpg">`;
+    const result = await transformHtml(input, 'index.html', defaultTransformOptions);
+
+    expect(result.code).toContain('width="200"');
+    expect(result.code).toMatch(/height="\d+"/);
+    expect(result.code).toContain('<img src="/good_boy_4x5.jpg">');
+  });
+
+  test('keeps the commas of src urls', async () => {
+    const imageDir = path.join(import.meta.dirname, 'public', 'w_400,h_300');
+    await mkdir(imageDir, { recursive: true });
+    try {
+      await copyFile(path.join(import.meta.dirname, 'public', 'good_boy.webp'), path.join(imageDir, 'good_boy.webp'));
+
+      const input = '<img src="/w_400,h_300/good_boy.webp" data-nocojs>';
+      const result = await transformHtml(input, 'index.html', defaultTransformOptions);
+
+      expect(result.code).toMatch(/<img src="data:image\/[^"]*"/);
+      expect(result.code).toContain('data-src="/w_400,h_300/good_boy.webp"');
+    } finally {
+      await rm(imageDir, { recursive: true, force: true });
+    }
+  });
+
+  test('returns documents without marked images unchanged', async () => {
+    const input = '<img src="/good_boy.webp">';
+    const result = await transformHtml(input, 'index.html', defaultTransformOptions);
+
+    expect(result.code).toBe(input);
+  });
+});
+
+describe('Stylesheets', () => {
+  test('replaces preview functions with their placeholder', async () => {
+    const input = `.hero {
+  background-image: nocojs-preview("/good_boy_4x5.jpg", blurred);
+  background-size: cover;
+}`;
+    const result = await transformCss(input, 'hero.module.css', defaultTransformOptions);
+
+    expect(result.code).toMatch(/background-image: url\("data:image\/[^"]*"\);/);
+    expect(result.code).toContain('background-size: cover;');
+    expect(result.code).not.toContain('nocojs-preview');
+  });
+
+  test('uses the placeholder type of the function', async () => {
+    const normal = await transformCss(
+      'a { background: nocojs-preview("/good_boy.webp"); }',
+      'a.css',
+      defaultTransformOptions,
+    );
+    const grayscale = await transformCss(
+      'a { background: nocojs-preview("/good_boy.webp", grayscale); }',
+      'a.css',
+      defaultTransformOptions,
+    );
+
+    expect(normal.code).toMatch(/url\("data:image\//);
+    expect(grayscale.code).toMatch(/url\("data:image\//);
+    expect(grayscale.code).not.toBe(normal.code);
+  });
+
+  test('replaces the url() values of marked custom properties', async () => {
+    const input = `:root {
+  --nocojs-hero: url(/good_boy.webp);
+  --hero: url("/good_boy.webp");
+}`;
+    const result = await transformCss(input, 'index.css', defaultTransformOptions);
+
+    expect(result.code).toMatch(/--nocojs-hero: url\("data:image\/[^"]*"\);/);
+    expect(result.code).toContain('--hero: url("/good_boy.webp");');
+  });
+
+  test('replaces nested preview functions', async () => {
+    const input = `@media (min-width: 768px) {
+  .hero { background-image: image-set(nocojs-preview(url(/good_boy.avif)) 1x); }
+}`;
+    const result = await transformCss(input, 'index.css', defaultTransformOptions);
+
+    expect(result.code).toMatch(/image-set\(url\("data:image\/[^"]*"\) 1x\)/);
+  });
+
+  test('maps the output to the stylesheet', async () => {
+    const input = `.hero {
+  background-image: nocojs-preview("/good_boy.webp");
+}`;
+    const result = await transformCss(input, 'hero.css', defaultTransformOptions);
+
+    const map = JSON.parse(result.map!);
+    expect(map.sources).toEqual(['hero.css']);
+    expect(map.mappings.split(';')[1]).not.toBe('');
+  });
+
+  test('returns stylesheets without previews unchanged', async () => {
+    const input = '.hero { background-image: url("/good_boy.webp"); }';
+    const result = await transformCss(input, 'index.css', defaultTransformOptions);
+
+    expect(result.code).toBe(input);
+    expect(result.map).toBeNull();
+  });
+});
+
+describe('Markdown documents', () => {
+  test('replaces images with enriched img elements', async () => {
+    const input = `# Post
+
+Look at this ![A *good* boy](/good_boy_4x5.jpg "Good boy").
+`;
+    const result = await transformMarkdown(input, 'post.md', defaultTransformOptions);
+
+    expect(result.code).toMatch(
+      /<img src="\/good_boy_4x5.jpg" alt="A good boy" title="Good boy" width="\d+" height="\d+" loading="lazy" style="background-image: url\(&quot;data:image\/[^&]*&quot;\); background-size: cover">/,
+    );
+    expect(result.code.startsWith('# Post\n\nLook at this <img')).toBe(true);
+  });
+
+  test('enriches img elements written in HTML', async () => {
+    const input = `<figure>
+  <img src="/good_boy.webp" alt="Good boy" width="300" loading="eager">
+</figure>
+
+Inline <img src="/good_boy.avif"> image.
+`;
+    const result = await transformMarkdown(input, 'post.md', {
+      ...defaultTransformOptions,
+      markdownImages: { placeholderAttribute: 'data-placeholder' },
+    });
+
+    expect(result.code).toMatch(/<img src="\/good_boy.webp" alt="Good boy" width="300" loading="eager" height="\d+" data-placeholder="data:image\//);
+    expect(result.code).toMatch(/<img src="\/good_boy.avif" width="\d+" height="\d+" loading="lazy" data-placeholder="data:image\//);
+  });
+
+  test('lists the placeholders in the front matter', async () => {
+    const input = `---
+title: Post
+---
+
+![Good boy](/good_boy_4x5.jpg)
+![Good boy again](/good_boy_4x5.jpg)
+`;
+    const result = await transformMarkdown(input, 'post.md', {
+      ...defaultTransformOptions,
+      markdownImages: { output: 'frontmatter' },
+    });
+
+    expect(result.code).toMatch(
+      /^---\ntitle: Post\nnocojs:\n  "\/good_boy_4x5.jpg":\n    placeholder: "data:image\/[^"]*"\n    width: \d+\n    height: \d+\n---\n/,
+    );
+    expect(result.code.match(/placeholder:/g)).toHaveLength(1);
+    expect(result.code).toContain('![Good boy](/good_boy_4x5.jpg)');
+  });
+
+  test('keeps a closing delimiter followed by spaces', async () => {
+    const input = '---\ntitle: Post\n---  \n\n![Good boy](/good_boy_4x5.jpg)\n';
+    const result = await transformMarkdown(input, 'post.md', {
+      ...defaultTransformOptions,
+      markdownImages: { output: 'frontmatter' },
+    });
+
+    expect(result.code).toMatch(/^---\ntitle: Post\nnocojs:\n[^]*\n    height: \d+\n---  \n\n!\[Good boy\]/);
+  });
+
+  test('adds a front matter to documents without one', async () => {
+    const input = '![Good boy](/good_boy.webp)\n';
+    const result = await transformMarkdown(input, 'post.md', {
+      ...defaultTransformOptions,
+      markdownImages: { output: 'frontmatter' },
+    });
+
+    expect(result.code).toMatch(/^---\nnocojs:\n  "\/good_boy.webp":\n/);
+    expect(result.code.endsWith('---\n\n![Good boy](/good_boy.webp)\n')).toBe(true);
+  });
+
+  test('returns documents without images unchanged', async () => {
+    const input = '# Post\n\n[A link](/good_boy.webp)\n';
+    const result = await transformMarkdown(input, 'post.md', defaultTransformOptions);
+
+    expect(result.code).toBe(input);
+    expect(result.map).toBeNull();
+  });
+});
@@ -266,4 +266,97 @@ describe('Preview options', async () => {
     const metadata = await sharpInstance.metadata();
     expect(metadata.format).toBe('png');
   });
+
+  test('crop: percentage crop is used for the svg dimensions', async () => {
+    const input = getInput({
+      url: '/good_boy_4x5.jpg',
+      previewOptions: {
+        crop: { x: 0, y: 0, width: 50, height: 50, unit: '%' },
+      },
+    });
+
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      cacheFileDir,
+    });
+
+    const imageSrc = result.code.match(/const img\s*=\s*"(.*?)";/);
+    expect(imageSrc).toBeDefined();
+    const sharpInstance = base64ToSharpImage(imageSrc![1]);
+    const metadata = await sharpInstance.metadata();
+
+    expect(metadata.width).toBe(200);
+    expect(metadata.height).toBe(250);
+  });
+
+  test('rotate: 90 degrees swaps the dimensions', async () => {
+    const input = getInput({
+      url: '/good_boy_4x5.jpg',
+      previewOptions: {
+        rotate: 90,
+        flip: 'horizontal',
+      },
+    });
+
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      cacheFileDir,
+    });
+
+    const imageSrc = result.code.match(/const img\s*=\s*"(.*?)";/);
+    expect(imageSrc).toBeDefined();
+    const sharpInstance = base64ToSharpImage(imageSrc![1]);
+    const metadata = await sharpInstance.metadata();
+
+    expect(metadata.width).toBe(500);
+    expect(metadata.height).toBe(400);
+  });
+
+  test('targetArea: picks dimensions from the pixel budget', async () => {
+    const input = getInput({
+      url: '/good_boy_4x5.jpg',
+      previewOptions: {
+        targetArea: 320,
+        wrapWithSvg: false,
+      },
+    });
+
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      cacheFileDir,
+    });
+
+    const imageSrc = result.code.match(/const img\s*=\s*"(.*?)";/);
+    expect(imageSrc).toBeDefined();
+    const sharpInstance = base64ToSharpImage(imageSrc![1]);
+    const metadata = await sharpInstance.metadata();
+
+    expect(metadata.width).toBe(16);
+    expect(metadata.height).toBe(20);
+  });
+
+  test('targetArea: both sides are scaled to fit maxSide', async () => {
+    const input = getInput({
+      url: '/good_boy_4x5.jpg',
+      previewOptions: {
+        targetArea: 320,
+        maxSide: 12,
+        wrapWithSvg: false,
+      },
+    });
+
+    const result = await transform(input, 'index.ts', {
+      ...defaultTransformOptions,
+      cacheFileDir,
+    });
+
+    const imageSrc = result.code.match(/const img\s*=\s*"(.*?)";/);
+    expect(imageSrc).toBeDefined();
+    const sharpInstance = base64ToSharpImage(imageSrc![1]);
+    const metadata = await sharpInstance.metadata();
+
+    // 16x20 for the pixel budget, scaled down to keep the aspect ratio
+    expect(metadata.width).toBe(10);
+    expect(metadata.height).toBe(12);
+  });
 });
@@ -149,3 +149,41 @@ export function verifyPreviewCall(code: string) {
     imageUpdated,
   };
 }
+
+const BASE64_CHARS = 'ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/';
+
+/**
+ * Decodes the `mappings` of a sourcemap into the segments of each generated line,
+ * as `[generatedColumn, sourceIndex, originalLine, originalColumn]` with absolute values.
+ */
+export const decodeMappings = (mappings: string): number[][][] => {
+  const state = [0, 0, 0, 0];
+
+  return mappings.split(';').map((line) => {
+    state[0] = 0;
+
+    return line
+      .split(',')
+      .filter(Boolean)
+      .map((segment) => {
+        const values: number[] = [];
+        let value = 0;
+        let shift = 0;
+        for (const char of segment) {
+          const digit = BASE64_CHARS.indexOf(char);
+          value += (digit & 31) << shift;
+          shift += 5;
+          if (!(digit & 32)) {
+            values.push(value & 1 ? -(value >> 1) : value >> 1);
+            value = 0;
+            shift = 0;
+          }
+        }
+
+        values.forEach((delta, index) => {
+          state[index] += delta;
+        });
+        return state.slice(0, values.length);
+      });
+  });
+};
@@ -1,11 +1,21 @@
 import * as path from 'path';
 import {
+  CropUnit,
+  FlipDirection,
   PlaceholderImageOutputKind,
   transform as rustTransform,
+  transformHtml as rustTransformHtml,
+  transformCss as rustTransformCss,
+  transformMarkdown as rustTransformMarkdown,
   getPlaceholder as rustGetPlaceholder,
   LogLevel,
   Log,
   GetPlaceholderOptions as RustGetPlaceholderOptions,
+  PreviewPreset as RustPreviewPreset,
+  JsxImageOptions,
+  HtmlImageOptions,
+  CssImageOptions,
+  MarkdownImageOptions,
 } from './index';
 
 const placeholderTypeToEnum = {
@@ -17,6 +27,17 @@ const placeholderTypeToEnum = {
   transparent: PlaceholderImageOutputKind.Transparent,
 } as const;
 
+const cropUnitToEnum = {
+  px: CropUnit.Pixels,
+  '%': CropUnit.Percent,
+} as const;
+
+const flipTypeToEnum = {
+  horizontal: FlipDirection.Horizontal,
+  vertical: FlipDirection.Vertical,
+  both: FlipDirection.Both,
+} as const;
+
 const logLevelTypeToEnum = {
   error: LogLevel.Error,
   info: LogLevel.Info,
@@ -28,6 +49,16 @@ export type PlaceholderType = keyof typeof placeholderTypeToEnum;
 
 export type LogLevelType = keyof typeof logLevelTypeToEnum;
 
+export type FlipType = keyof typeof flipTypeToEnum;
+
+export interface CropOptions {
+  x: number;
+  y: number;
+  width: number;
+  height: number;
+  unit?: keyof typeof cropUnitToEnum;
+}
+
 export interface PreviewOptions {
   placeholderType?: PlaceholderType;
   replaceFunctionCall?: boolean;
@@ -35,16 +66,87 @@ export interface PreviewOptions {
   width?: number;
   height?: number;
   wrapWithSvg?: boolean;
+  trim?: boolean;
+  trimTolerance?: number;
+  crop?: CropOptions;
+  rotate?: 90 | 180 | 270;
+  flip?: FlipType;
+  targetArea?: number;
+  minSide?: number;
+  maxSide?: number;
+  maxBytes?: number;
+  targetSimilarity?: number;
 }
 
-export interface TransformOptions extends PreviewOptions {
+export interface ImportSource {
+  source: string;
+  // Exported function names mapped to the preview options applied to their calls.
+  // With `meta`, calls are replaced with an object of image metadata instead of the placeholder.
+  names: Record<string, PreviewOptions & { meta?: boolean }>;
+}
+
+const toPreviewPreset = (options: PreviewOptions & { meta?: boolean }): RustPreviewPreset => ({
+  ...options,
+  placeholderType: options.placeholderType ? placeholderTypeToEnum[options.placeholderType] : undefined,
+  crop: options.crop ? { ...options.crop, unit: cropUnitToEnum[options.crop.unit ?? 'px'] } : undefined,
+  flip: options.flip ? flipTypeToEnum[options.flip] : undefined,
+});
+
+export interface TransformOptions extends Omit<PreviewOptions, 'crop' | 'rotate' | 'flip'> {
   publicDir?: string;
   cacheFileDir?: string;
   logLevel?: LogLevelType;
   sourcemapFilePath?: string;
+  preserveFormatting?: boolean;
+  inputSourcemap?: string;
+  importSources?: ImportSource[];
+  define?: Record<string, string>;
+  alias?: Record<string, string>;
+  publicDirs?: string[];
+  jsxImages?: JsxImageOptions;
+  nextImageBlur?: boolean;
+  htmlImages?: HtmlImageOptions;
+  cssImages?: CssImageOptions;
+  markdownImages?: MarkdownImageOptions;
   forceSupportedFileExtensions?: string[];
 }
 
+const toRustTransformOptions = (filePath: string, options?: TransformOptions) => ({
+  placeholderType: options?.placeholderType
+    ? placeholderTypeToEnum[options.placeholderType]
+    : PlaceholderImageOutputKind.Normal,
+  replaceFunctionCall: options?.replaceFunctionCall ?? true,
+  cache: options?.cache ?? true,
+  publicDir: options?.publicDir ?? path.join(process.cwd(), 'public'),
+  cacheFileDir: options?.cacheFileDir ?? path.join(process.cwd(), '.nocojs'),
+  logLevel: options?.logLevel ? logLevelTypeToEnum[options.logLevel] : LogLevel.Error,
+  width: options?.width,
+  height: options?.height,
+  sourcemapFilePath: options?.sourcemapFilePath ?? filePath,
+  wrapWithSvg: options?.wrapWithSvg ?? true,
+  trim: options?.trim ?? false,
+  trimTolerance: options?.trimTolerance,
+  targetArea: options?.targetArea,
+  minSide: options?.minSide,
+  maxSide: options?.maxSide,
+  maxBytes: options?.maxBytes,
+  targetSimilarity: options?.targetSimilarity,
+  preserveFormatting: options?.preserveFormatting ?? false,
+  inputSourcemap: options?.inputSourcemap,
+  importSources: options?.importSources?.map(({ source, names }) => ({
+    source,
+    names: Object.fromEntries(Object.entries(names).map(([name, preset]) => [name, toPreviewPreset(preset)])),
+  })),
+  define: options?.define,
+  alias: options?.alias,
+  publicDirs: options?.publicDirs,
+  jsxImages: options?.jsxImages,
+  nextImageBlur: options?.nextImageBlur,
+  htmlImages: options?.htmlImages,
+  cssImages: options?.cssImages,
+  markdownImages: options?.markdownImages,
+});
+
 export const transform = async (
   code: string,
   filePath: string,
@@ -54,7 +156,8 @@ export const transform = async (
   map: string | null;
   logs: Log[];
 }> => {
-  const { forceSupportedFileExtensions = ['.svelte', '.vue'] } = options ?? {};
+  // Vue, Svelte and Astro components are handled natively, other extensions can be parsed as TypeScript
+  const { forceSupportedFileExtensions = [] } = options ?? {};
 
   let resolvedFilePath = filePath;
   if (forceSupportedFileExtensions.some((ext) => filePath.endsWith(ext))) {
@@ -62,20 +165,7 @@ export const transform = async (
   }
 
   try {
-    const result = await rustTransform(code, resolvedFilePath, {
-      placeholderType: options?.placeholderType
-        ? placeholderTypeToEnum[options.placeholderType]
-        : PlaceholderImageOutputKind.Normal,
-      replaceFunctionCall: options?.replaceFunctionCall ?? true,
-      cache: options?.cache ?? true,
-      publicDir: options?.publicDir ?? path.join(process.cwd(), 'public'),
-      cacheFileDir: options?.cacheFileDir ?? path.join(process.cwd(), '.nocojs'),
-      logLevel: options?.logLevel ? logLevelTypeToEnum[options.logLevel] : LogLevel.Error,
-      width: options?.width,
-      height: options?.height,
-      sourcemapFilePath: options?.sourcemapFilePath ?? filePath,
-      wrapWithSvg: options?.wrapWithSvg ?? true,
-    });
+    const result = await rustTransform(code, resolvedFilePath, toRustTransformOptions(filePath, options));
 
     if (!result) {
       return {
@@ -100,8 +190,102 @@ export const transform = async (
   }
 };
 
-export interface GetPlaceholderOptions extends Omit<RustGetPlaceholderOptions, 'placeholderType'> {
+/**
+ * Enriches the `<img>` and `<source>` elements of an HTML document marked with `data-nocojs`
+ * (see `htmlImages`) with their placeholder and dimensions.
+ */
+export const transformHtml = async (
+  code: string,
+  filePath: string,
+  options?: TransformOptions,
+): Promise<{
+  code: string;
+  logs: Log[];
+}> => {
+  try {
+    const result = await rustTransformHtml(code, filePath, toRustTransformOptions(filePath, options));
+
+    return {
+      code: result?.code ?? code,
+      logs: result?.logs ?? [],
+    };
+  } catch (error) {
+    console.error(`[nocojs] Error during HTML transformation: ${error} file: ${filePath}`);
+    return {
+      code,
+      logs: [],
+    };
+  }
+};
+
+/**
+ * Replaces `nocojs-preview("/hero.jpg", blurred)` functions, and the `url()` values of custom properties
+ * starting with `--nocojs` (see `cssImages`), with the placeholder of their image.
+ */
+export const transformCss = async (
+  code: string,
+  filePath: string,
+  options?: TransformOptions,
+): Promise<{
+  code: string;
+  map: string | null;
+  logs: Log[];
+}> => {
+  try {
+    const result = await rustTransformCss(code, filePath, toRustTransformOptions(filePath, options));
+
+    return {
+      code: result?.code ?? code,
+      map: result?.sourcemap ?? null,
+      logs: result?.logs ?? [],
+    };
+  } catch (error) {
+    console.error(`[nocojs] Error during CSS transformation: ${error} file: ${filePath}`);
+    return {
+      code,
+      map: null,
+      logs: [],
+    };
+  }
+};
+
+/**
+ * Enriches the `![alt](src)` images and the `<img>` elements of a Markdown document with their placeholder,
+ * dimensions and `loading="lazy"`, or lists their placeholders in its front matter (see `markdownImages`).
+ */
+export const transformMarkdown = async (
+  code: string,
+  filePath: string,
+  options?: TransformOptions,
+): Promise<{
+  code: string;
+  map: string | null;
+  logs: Log[];
+}> => {
+  try {
+    const result = await rustTransformMarkdown(code, filePath, toRustTransformOptions(filePath, options));
+
+    return {
+      code: result?.code ?? code,
+      map: result?.sourcemap ?? null,
+      logs: result?.logs ?? [],
+    };
+  } catch (error) {
+    console.error(`[nocojs] Error during Markdown transformation: ${error} file: ${filePath}`);
+    return {
+      code,
+      map: null,
+      logs: [],
+    };
+  }
+};
+
+export interface GetPlaceholderOptions
+  extends Omit<RustGetPlaceholderOptions, 'placeholderType' | 'crop' | 'rotate' | 'flip'> {
   placeholderType?: PlaceholderType;
+  crop?: CropOptions;
+  rotate?: 90 | 180 | 270;
+  flip?: FlipType;
 }
 
 export const getPlaceholder = async (url: string, options?: GetPlaceholderOptions) => {
@@ -110,5 +294,9 @@ export const getPlaceholder = async (url: string, options?: GetPlaceholderOption
     placeholderType: options?.placeholderType
       ? placeholderTypeToEnum[options.placeholderType]
       : PlaceholderImageOutputKind.Normal,
+    crop: options?.crop
+      ? { ...options.crop, unit: cropUnitToEnum[options.crop.unit ?? 'px'] }
+      : undefined,
+    flip: options?.flip ? flipTypeToEnum[options.flip] : undefined,
   });
 };
@@ -1,5 +1,41 @@
 /* auto-generated by NAPI-RS */
 /* eslint-disable */
+/**
+ * Crop rectangle applied to the source image before resizing.
+ * Values are source pixels, or percentages of the source dimensions when `unit` is `Percent`.
+ */
+export interface CropRect {
+  x: number
+  y: number
+  width: number
+  height: number
+  unit?: CropUnit
+}
+
+export declare const enum CropUnit {
+  Pixels = 0,
+  Percent = 1
+}
+
+export interface CssImageOptions {
+  /**
+   * Name of the function replaced with the placeholder of its image, eg: `nocojs-preview("/hero.jpg", blurred)`.
+   * Defaults to "nocojs-preview".
+   */
+  functionName?: string
+  /**
+   * The `url()` values of custom properties starting with this name are replaced with their placeholder.
+   * Defaults to "--nocojs".
+   */
+  markerProperty?: string
+}
+
+export declare const enum FlipDirection {
+  Horizontal = 0,
+  Vertical = 1,
+  Both = 2
+}
+
 export declare function getPlaceholder(url: string, options: GetPlaceholderOptions): GetPlaceholderOutput
 
 export interface GetPlaceholderOptions {
@@ -9,12 +45,65 @@ export interface GetPlaceholderOptions {
   cacheFileDir?: string
   cache?: boolean
   wrapWithSvg?: boolean
+  trim?: boolean
+  trimTolerance?: number
+  crop?: CropRect
+  rotate?: number
+  flip?: FlipDirection
+  targetArea?: number
+  minSide?: number
+  maxSide?: number
+  maxBytes?: number
+  targetSimilarity?: number
 }
 
 export interface GetPlaceholderOutput {
   placeholder: string
   logs: Array<Log>
   isError: boolean
+  /** Region of the source image the placeholder covers, when borders were trimmed. */
+  trimBox?: TrimBox
+  /** Parameters chosen to fit the placeholder in `max_bytes`, when a budget was given. */
+  tuning?: PlaceholderTuning
+  /** SSIM of the placeholder against the original, when a target similarity was given. */
+  similarity?: number
+}
+
+/** A module exporting preview functions, eg: a design system re-exporting "preview" helpers. */
+export interface HtmlImageOptions {
+  /**
+   * `<img>` and `<source>` elements with this attribute are enriched, the attribute is then removed.
+   * Defaults to "data-nocojs".
+   */
+  markerAttribute?: string
+  /**
+   * Where the placeholder goes. Defaults to "src", the original URL is moved to `data-src`.
+   * With "style", the placeholder becomes the background image of `<img>` elements.
+   * `<source>` elements can't have a background image, their `srcset` receives the placeholder either way.
+   */
+  placeholderAttribute?: 'src' | 'style'
+}
+
+export interface ImportSource {
+  source: string
+  /** Exported function names mapped to the preset applied to their calls. */
+  names: Record<string, PreviewPreset>
+}
+
+/** Enrichment of JSX image elements, eg: `<img src="/a.jpg" data-nocojs />`. */
+export interface JsxImageOptions {
+  /**
+   * Elements with this attribute are enriched, the attribute is then removed.
+   * Defaults to "data-nocojs".
+   */
+  markerAttribute?: string
+  /** Element or component names enriched without the marker attribute, eg: `["Image"]`. */
+  components?: Array<string>
+  /**
+   * Attribute receiving the placeholder. Defaults to "data-placeholder".
+   * With "style", the placeholder becomes the background image of the element.
+   */
+  placeholderAttribute?: string
 }
 
 export interface Log {
@@ -29,6 +118,25 @@ export declare const enum LogLevel {
   Verbose = 3
 }
 
+export interface MarkdownImageOptions {
+  /**
+   * "html" replaces the images with `<img>` elements holding their placeholder and dimensions,
+   * "frontmatter" lists the placeholders in the front matter instead. Defaults to "html".
+   */
+  output?: string
+  /**
+   * Attribute of the `<img>` elements receiving the placeholder, or "style" for a background image.
+   * Defaults to "style".
+   */
+  placeholderAttribute?: string
+}
+
+export declare const enum PlaceholderFormat {
+  Png = 0,
+  Jpeg = 1,
+  Webp = 2
+}
+
 export declare const enum PlaceholderImageOutputKind {
   Normal = 0,
   Blurred = 1,
@@ -38,6 +146,19 @@ export declare const enum PlaceholderImageOutputKind {
   Transparent = 5
 }
 
+/** Parameters chosen when a placeholder is tuned to fit a byte budget (`max_bytes`). */
+export interface PlaceholderTuning {
+  width: number
+  height: number
+  format: PlaceholderFormat
+  /** Encoder quality, only set for lossy formats. */
+  quality?: number
+  /** Bits kept per colour channel before encoding. */
+  colorBits: number
+  /** Size of the final placeholder string, including the SVG wrapper when enabled. */
+  bytes: number
+}
+
 export interface PreviewOptions {
   width?: number
   height?: number
@@ -45,10 +166,60 @@ export interface PreviewOptions {
   replaceFunctionCall: boolean
   cache: boolean
   wrapWithSvg: boolean
+  trim: boolean
+  trimTolerance: number
+  crop?: CropRect
+  rotate?: number
+  flip?: FlipDirection
+  targetArea?: number
+  minSide?: number
+  maxSide?: number
+  maxBytes?: number
+  targetSimilarity?: number
+}
+
+/**
+ * Preview options applied to every call of a function from `import_sources`.
+ * Options passed to the call itself take precedence.
+ */
+export interface PreviewPreset {
+  width?: number
+  height?: number
+  placeholderType?: PlaceholderImageOutputKind
+  replaceFunctionCall?: boolean
+  cache?: boolean
+  wrapWithSvg?: boolean
+  trim?: boolean
+  trimTolerance?: number
+  crop?: CropRect
+  rotate?: number
+  flip?: FlipDirection
+  targetArea?: number
+  minSide?: number
+  maxSide?: number
+  maxBytes?: number
+  targetSimilarity?: number
+  /**
+   * Replace calls with an object of image metadata
+   * (`{ placeholder, width, height, aspectRatio, dominantColor, format }`) instead of the placeholder.
+   */
+  meta?: boolean
 }
 
 export declare function transform(code: string, filePath: string, options: TransformOptions): TransformOutput | null
 
+/** Replaces the preview functions and the `url()` values of marked custom properties of a stylesheet. */
+export declare function transformCss(code: string, filePath: string, options: TransformOptions): TransformOutput | null
+
+/** Enriches the `<img>` and `<source>` elements of an HTML document marked with `html_images.marker_attribute`. */
+export declare function transformHtml(code: string, filePath: string, options: TransformOptions): TransformOutput | null
+
+/**
+ * Enriches the `![alt](src)` images and the `<img>` elements of a Markdown document,
+ * or lists their placeholders in its front matter.
+ */
+export declare function transformMarkdown(code: string, filePath: string, options: TransformOptions): TransformOutput | null
+
 export interface TransformOptions {
   placeholderType?: PlaceholderImageOutputKind
   replaceFunctionCall?: boolean
@@ -60,6 +231,56 @@ export interface TransformOptions {
   height?: number
   sourcemapFilePath?: string
   wrapWithSvg?: boolean
+  trim?: boolean
+  trimTolerance?: number
+  targetArea?: number
+  minSide?: number
+  maxSide?: number
+  maxBytes?: number
+  targetSimilarity?: number
+  /**
+   * Splice the replacements into the original source instead of re-printing the whole module.
+   * Comments and formatting are kept and the sourcemap is near-identity.
+   */
+  preserveFormatting?: boolean
+  /**
+   * Sourcemap (JSON string) of the code passed to `transform`, e.g. from an earlier loader.
+   * When set, the returned sourcemap is composed with it and points at the original sources.
+   */
+  inputSourcemap?: string
+  /**
+   * Modules whose functions are treated as "preview" calls.
+   * Defaults to the "preview" function of "@nocojs/client".
+   */
+  importSources?: Array<ImportSource>
+  /**
+   * Values substituted for globals and member expressions when resolving preview arguments,
+   * eg: `process.env.CDN_URL` or `import.meta.env.BASE_URL`.
+   */
+  define?: Record<string, string>
+  /**
+   * Path aliases for local images, eg: `{ "@/assets": "./src/assets" }`.
+   * Relative targets are resolved against the working directory.
+   */
+  alias?: Record<string, string>
+  /** Public directories searched after `public_dir`, in order. */
+  publicDirs?: Array<string>
+  /**
+   * Enriches JSX image elements with their placeholder and dimensions.
+   * Disabled unless set.
+   */
+  jsxImages?: JsxImageOptions
+  /**
+   * Injects `blurDataURL` into `next/image` elements using `placeholder="blur"`
+   * with a remote or public `src`. Disabled unless set.
+   */
+  nextImageBlur?: boolean
+  /** Options of `transform_html`, which enriches the marked images of HTML documents. */
+  htmlImages?: HtmlImageOptions
+  /** Options of `transform_css`, which replaces the images of stylesheets with their placeholder. */
+  cssImages?: CssImageOptions
+  /** Options of `transform_markdown`, which enriches the images of Markdown documents. */
+  markdownImages?: MarkdownImageOptions
 }
 
 export interface TransformOutput {
@@ -67,3 +288,14 @@ export interface TransformOutput {
   sourcemap?: string
   logs?: Array<Log>
 }
+
+/**
+ * Region of the source image that was kept after trimming uniform borders.
+ * When a crop, rotation or flip is requested, the box is relative to the image after those.
+ */
+export interface TrimBox {
+  x: number
+  y: number
+  width: number
+  height: number
+}
@@ -397,3 +397,6 @@ module.exports.getPlaceholder = nativeBinding.getPlaceholder
 module.exports.LogLevel = nativeBinding.LogLevel
 module.exports.PlaceholderImageOutputKind = nativeBinding.PlaceholderImageOutputKind
 module.exports.transform = nativeBinding.transform
+module.exports.transformHtml = nativeBinding.transformHtml
+module.exports.transformCss = nativeBinding.transformCss
+module.exports.transformMarkdown = nativeBinding.transformMarkdown
@@ -0,0 +1,423 @@
+use std::{ops::Range, path::Path};
+
+use oxc::span::{SourceType, Span};
+
+use crate::source_patch::SourcePatcher;
+
+/// Component files whose scripts and markup expressions are transformed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComponentKind {
+  Vue,
+  Svelte,
+  Astro,
+}
+
+impl ComponentKind {
+  pub fn from_path(path: &str) -> Option<ComponentKind> {
+    match Path::new(path).extension()?.to_str()? {
+      "vue" => Some(ComponentKind::Vue),
+      "svelte" => Some(ComponentKind::Svelte),
+      "astro" => Some(ComponentKind::Astro),
+      _ => None,
+    }
+  }
+}
+
+/// A part of the component copied into the virtual module.
+#[derive(Debug)]
+struct Segment {
+  virtual_start: u32,
+  original_start: u32,
+  len: u32,
+  /// Quote delimiting the attribute the segment is in, eg: `"` for `:src="preview('/a.jpg')"`.
+  quote: Option<char>,
+}
+
+/// The JavaScript of a component: its `<script>` blocks (and the Astro frontmatter) followed by
+/// its markup expressions, each as a statement, eg: `;(preview('/a.jpg'));`.
+/// The code is transformed as a module, and the patches are moved back to the component.
+#[derive(Debug)]
+pub struct VirtualModule {
+  pub code: String,
+  pub source_type: SourceType,
+  segments: Vec<Segment>,
+}
+
+impl VirtualModule {
+  /// Extracts the JavaScript of a component. Handled markup expressions:
+  /// - Vue: `{{ expr }}` interpolations and `:attr="expr"` / `v-bind:attr="expr"` bindings of the `<template>`
+  /// - Svelte and Astro: `{expr}` in the markup, including attributes, eg: `src={expr}`.
+  ///   Svelte blocks and tags, eg: `{#if}` or `{@html}`, and spreads are skipped.
+  ///
+  /// Returns None for components without scripts, eg: already compiled to JavaScript by an earlier plugin.
+  pub fn new(source: &str, kind: ComponentKind) -> Option<VirtualModule> {
+    let mut module = VirtualModule {
+      code: String::new(),
+      source_type: SourceType::mjs(),
+      segments: vec![],
+    };
+
+    let mut is_typescript = kind == ComponentKind::Astro;
+    let mut is_jsx = kind == ComponentKind::Astro;
+    // Script and style blocks, which aren't markup
+    let mut excluded = vec![];
+
+    if kind == ComponentKind::Astro {
+      if let Some((frontmatter, content)) = find_frontmatter(source) {
+        module.push_segment(source, content, None);
+        excluded.push(frontmatter);
+      }
+    }
+
+    for block in find_blocks(source, "script") {
+      let lang = get_attribute_value(&source[block.attributes.clone()], "lang");
+      is_typescript |= lang.is_some_and(|lang| lang.starts_with("ts"));
+      is_jsx |= lang.is_some_and(|lang| lang.ends_with("sx"));
+      module.push_segment(source, block.content, None);
+      excluded.push(block.range);
+    }
+    if module.segments.is_empty() {
+      return None;
+    }
+    excluded.extend(
+      find_blocks(source, "style")
+        .into_iter()
+        .map(|block| block.range),
+    );
+
+    module.source_type = SourceType::mjs()
+      .with_typescript(is_typescript)
+      .with_jsx(is_jsx);
+
+    match kind {
+      ComponentKind::Vue => {
+        if let Some(template) = find_template(source) {
+          for (expression, quote) in find_vue_expressions(source, template) {
+            module.push_expression(source, expression, quote);
+          }
+        }
+      }
+      ComponentKind::Svelte | ComponentKind::Astro => {
+        for expression in find_brace_expressions(source, &excluded) {
+          module.push_expression(source, expression, None);
+        }
+      }
+    }
+
+    Some(module)
+  }
+
+  fn push_segment(&mut self, source: &str, range: Range<usize>, quote: Option<char>) {
+    self.segments.push(Segment {
+      virtual_start: self.code.len() as u32,
+      original_start: range.start as u32,
+      len: range.len() as u32,
+      quote,
+    });
+    self.code.push_str(&source[range]);
+    self.code.push('\n');
+  }
+
+  fn push_expression(&mut self, source: &str, range: Range<usize>, quote: Option<char>) {
+    if source[range.clone()].trim().is_empty() {
+      return;
+    }
+    self.code.push_str(";(");
+    self.push_segment(source, range, quote);
+    self.code.push_str(");\n");
+  }
+
+  /// Moves the patches of the virtual module to the component.
+  /// Patches that don't fall within a single part of the component are dropped.
+  /// Quotes delimiting an attribute are escaped, eg: `"` becomes `&quot;` in `:src="..."`.
+  pub fn remap(&self, patcher: &SourcePatcher) -> SourcePatcher {
+    patcher.remap(|span, replacement| {
+      let segment = self.find_segment(span)?;
+      let offset = segment.original_start as i64 - segment.virtual_start as i64;
+      let span = Span::new(
+        (span.start as i64 + offset) as u32,
+        (span.end as i64 + offset) as u32,
+      );
+
+      let replacement = match segment.quote {
+        Some(quote) if replacement.contains(quote) => replacement
+          .replace('&', "&amp;")
+          .replace('"', "&quot;")
+          .replace('\'', "&#39;"),
+        _ => replacement.to_string(),
+      };
+      Some((span, replacement))
+    })
+  }
+
+  /// Returns the offset in the component of an offset of the virtual module,
+  /// or None for the code added around the markup expressions.
+  pub fn original_offset(&self, offset: u32) -> Option<u32> {
+    let segment = self.find_segment(Span::new(offset, offset))?;
+    Some(segment.original_start + offset - segment.virtual_start)
+  }
+
+  fn find_segment(&self, span: Span) -> Option<&Segment> {
+    self.segments.iter().find(|segment| {
+      span.start >= segment.virtual_start && span.end <= segment.virtual_start + segment.len
+    })
+  }
+}
+
+/// An element whose content is kept as is, eg: `<script lang="ts">...</script>`.
+struct Block {
+  /// The whole element
+  range: Range<usize>,
+  /// The attributes of the opening tag
+  attributes: Range<usize>,
+  content: Range<usize>,
+}
+
+/// Finds the top-level `<tag>...</tag>` elements whose content is raw text, eg: `script` or `style`.
+fn find_blocks(source: &str, tag: &str) -> Vec<Block> {
+  let opening = format!("<{}", tag);
+  let closing = format!("</{}>", tag);
+  let mut blocks = vec![];
+  let mut cursor = 0;
+
+  while let Some(found) = source[cursor..].find(&opening) {
+    let start = cursor + found;
+    let attributes_start = start + opening.len();
+    cursor = attributes_start;
+    // Eg: <scripts> or <style-guide>
+    if !source[attributes_start..]
+      .starts_with(|ch: char| ch.is_ascii_whitespace() || ch == '>' || ch == '/')
+    {
+      continue;
+    }
+
+    let Some(attributes_end) = find_tag_end(source, attributes_start) else {
+      break;
+    };
+    let content_start = attributes_end + 1;
+    let Some(content_len) = source[content_start..].find(&closing) else {
+      break;
+    };
+    let content_end = content_start + content_len;
+    cursor = content_end + closing.len();
+
+    blocks.push(Block {
+      range: start..cursor,
+      attributes: attributes_start..attributes_end,
+      content: content_start..content_end,
+    });
+  }
+
+  blocks
+}
+
+/// Returns the position of the `>` closing the tag whose attributes start at `start`.
+fn find_tag_end(source: &str, start: usize) -> Option<usize> {
+  let bytes = source.as_bytes();
+  let mut quote = None;
+  for (index, &byte) in bytes.iter().enumerate().skip(start) {
+    match (quote, byte) {
+      (None, b'"' | b'\'') => quote = Some(byte),
+      (Some(open), _) if open == byte => quote = None,
+      (None, b'>') => return Some(index),
+      _ => {}
+    }
+  }
+  None
+}
+
+/// Reads an attribute of an opening tag, eg: `ts` for `lang="ts"`.
+fn get_attribute_value<'s>(attributes: &'s str, name: &str) -> Option<&'s str> {
+  let mut rest = attributes;
+  while let Some(found) = rest.find(name) {
+    let is_name_start = rest[..found]
+      .chars()
+      .next_back()
+      .is_none_or(|ch| ch.is_ascii_whitespace());
+    rest = &rest[found + name.len()..];
+    let Some(value) = rest.trim_start().strip_prefix('=') else {
+      continue;
+    };
+    if !is_name_start {
+      continue;
+    }
+
+    let value = value.trim_start();
+    return match value.chars().next() {
+      Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+      _ => value
+        .split(|ch: char| ch.is_ascii_whitespace() || ch == '/')
+        .next(),
+    };
+  }
+  None
+}
+
+/// Finds the Astro frontmatter, the code between the `---` fences at the start of the file.
+/// Returns the whole frontmatter and its content.
+fn find_frontmatter(source: &str) -> Option<(Range<usize>, Range<usize>)> {
+  let start = source.len() - source.trim_start().len();
+  let content_start = start + source[start..].strip_prefix("---")?.find('\n')? + 4;
+  let mut line_start = content_start;
+  for line in source[content_start..].split_inclusive('\n') {
+    if line.trim_end() == "---" {
+      return Some((start..line_start + line.len(), content_start..line_start));
+    }
+    line_start += line.len();
+  }
+  None
+}
+
+/// Finds the content of the Vue `<template>`, from the first opening tag to the last closing tag,
+/// so that nested templates, eg: `<template v-if="...">`, are included.
+fn find_template(source: &str) -> Option<Range<usize>> {
+  let start = find_blocks(source, "template").first()?.attributes.start;
+  let content_start = find_tag_end(source, start)? + 1;
+  let content_end = source.rfind("</template>")?;
+  (content_start <= content_end).then_some(content_start..content_end)
+}
+
+/// Finds the `{{ expr }}` interpolations and the values of the `:attr` / `v-bind` bindings
+/// of a Vue template, with the quote delimiting the attribute value.
+fn find_vue_expressions(source: &str, template: Range<usize>) -> Vec<(Range<usize>, Option<char>)> {
+  let bytes = source.as_bytes();
+  let mut expressions = vec![];
+  let mut cursor = template.start;
+
+  while cursor < template.end {
+    if bytes[cursor..].starts_with(b"{{") {
+      let start = cursor + 2;
+      let Some(len) = source[start..template.end].find("}}") else {
+        break;
+      };
+      expressions.push((start..start + len, None));
+      cursor = start + len + 2;
+    } else if bytes[cursor..].starts_with(b"<!--") {
+      cursor = source[cursor..template.end]
+        .find("-->")
+        .map_or(template.end, |len| cursor + len + 3);
+    } else if bytes[cursor] == b'<' && bytes.get(cursor + 1).is_some_and(u8::is_ascii_alphabetic) {
+      let Some(tag_end) = find_tag_end(source, cursor) else {
+        break;
+      };
+      expressions.extend(find_vue_bindings(source, cursor..tag_end));
+      cursor = tag_end + 1;
+    } else {
+      cursor += next_char_len(source, cursor);
+    }
+  }
+
+  expressions
+}
+
+/// Finds the values of the bindings of an opening tag, eg: `preview('/a.jpg')` for `:src="preview('/a.jpg')"`.
+fn find_vue_bindings(source: &str, tag: Range<usize>) -> Vec<(Range<usize>, Option<char>)> {
+  let bytes = source.as_bytes();
+  let mut bindings = vec![];
+  let mut cursor = tag.start;
+
+  while cursor < tag.end {
+    let Some(found) = source[cursor..tag.end].find('=') else {
+      break;
+    };
+    let equals = cursor + found;
+    let name = source[tag.start..equals]
+      .trim_end()
+      .rsplit(|ch: char| ch.is_ascii_whitespace())
+      .next()
+      .unwrap_or_default();
+
+    let value_start =
+      equals + 1 + (source[equals + 1..].len() - source[equals + 1..].trim_start().len());
+    let (value, quote) = match bytes.get(value_start) {
+      Some(&quote @ (b'"' | b'\'')) => {
+        let end = source[value_start + 1..tag.end]
+          .find(quote as char)
+          .map_or(tag.end, |len| value_start + 1 + len);
+        (value_start + 1..end, Some(quote as char))
+      }
+      _ => {
+        let end = source[value_start..tag.end]
+          .find(|ch: char| ch.is_ascii_whitespace() || ch == '>')
+          .map_or(tag.end, |len| value_start + len);
+        (value_start..end, None)
+      }
+    };
+    cursor = value.end + 1;
+
+    if name.starts_with(':') || name == "v-bind" || name.starts_with("v-bind:") {
+      bindings.push((value, quote));
+    }
+  }
+
+  bindings
+}
+
+/// Finds the `{expr}` expressions of Svelte and Astro markup outside of the `excluded` ranges.
+fn find_brace_expressions(source: &str, excluded: &[Range<usize>]) -> Vec<Range<usize>> {
+  let bytes = source.as_bytes();
+  let mut expressions = vec![];
+  let mut cursor = 0;
+
+  while cursor < bytes.len() {
+    if let Some(range) = excluded.iter().find(|range| range.contains(&cursor)) {
+      cursor = range.end;
+      continue;
+    }
+    if bytes[cursor..].starts_with(b"<!--") {
+      cursor = source[cursor..]
+        .find("-->")
+        .map_or(bytes.len(), |len| cursor + len + 3);
+      continue;
+    }
+    if bytes[cursor] != b'{' {
+      cursor += next_char_len(source, cursor);
+      continue;
+    }
+
+    let start = cursor + 1;
+    let Some(end) = find_closing_brace(source, start) else {
+      break;
+    };
+    cursor = end + 1;
+
+    // Eg: {#if cond}, {:else}, {/if}, {@html html} or {...props}
+    let expression = source[start..end].trim_start();
+    if !expression.starts_with(['#', ':', '/', '@']) && !expression.starts_with("...") {
+      expressions.push(start..end);
+    }
+  }
+
+  expressions
+}
+
+/// Length in bytes of the character at `index`, so that scans stay on character boundaries.
+fn next_char_len(source: &str, index: usize) -> usize {
+  source[index..].chars().next().map_or(1, char::len_utf8)
+}
+
+/// Returns the position of the `}` closing the expression starting at `start`.
+/// Nested braces and braces in string literals are skipped.
+fn find_closing_brace(source: &str, start: usize) -> Option<usize> {
+  let bytes = source.as_bytes();
+  let mut depth = 0;
+  let mut index = start;
+
+  while index < bytes.len() {
+    match bytes[index] {
+      quote @ (b'"' | b'\'' | b'`') => {
+        index += 1;
+        while index < bytes.len() && bytes[index] != quote {
+          index += if bytes[index] == b'\\' { 2 } else { 1 };
+        }
+      }
+      b'{' => depth += 1,
+      b'}' if depth == 0 => return Some(index),
+      b'}' => depth -= 1,
+      _ => {}
+    }
+    index += 1;
+  }
+
+  None
+}
@@ -0,0 +1,242 @@
+use cssparser::{ParseError, Parser, ParserInput, Token};
+use napi_derive::napi;
+use oxc::span::Span;
+use std::time::Instant;
+
+use crate::{
+  image_queue::ImageQueue,
+  log::{self, create_log, set_log_level, LogLevel},
+  placeholder_image::PlaceholderImageOutputKind,
+  resolve::resolve_image_specifier,
+  source_patch::SourcePatcher,
+  sourcemap::compose_input_sourcemap,
+  transform::{PreviewOptions, TransformOptions, TransformOutput},
+};
+
+const DEFAULT_CSS_FUNCTION_NAME: &str = "nocojs-preview";
+const DEFAULT_CSS_MARKER_PROPERTY: &str = "--nocojs";
+
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct CssImageOptions {
+  /// Name of the function replaced with the placeholder of its image, eg: `nocojs-preview("/hero.jpg", blurred)`.
+  /// Defaults to "nocojs-preview".
+  pub function_name: Option<String>,
+  /// The `url()` values of custom properties starting with this name are replaced with their placeholder.
+  /// Defaults to "--nocojs".
+  pub marker_property: Option<String>,
+}
+
+impl CssImageOptions {
+  fn function_name(&self) -> &str {
+    self
+      .function_name
+      .as_deref()
+      .unwrap_or(DEFAULT_CSS_FUNCTION_NAME)
+  }
+
+  fn marker_property(&self) -> &str {
+    self
+      .marker_property
+      .as_deref()
+      .unwrap_or(DEFAULT_CSS_MARKER_PROPERTY)
+  }
+}
+
+/// An image of the stylesheet. `span` covers the value replaced with `url("<placeholder>")`.
+struct CssImage {
+  span: Span,
+  specifier: String,
+  placeholder_type: Option<String>,
+}
+
+/// Replaces the images of a stylesheet with their placeholder, eg:
+/// `background-image: nocojs-preview("/hero.jpg", blurred)` or `--nocojs-hero: url("/hero.jpg")`
+/// both become `url("data:...")`.
+///
+/// Like `transform`, the images are first queued, then the stylesheet is patched once they're processed.
+pub async fn transform_css(
+  code: String,
+  file_path: String,
+  options: TransformOptions,
+) -> Result<Option<TransformOutput>, Box<dyn std::error::Error>> {
+  let css_images = options.css_images.clone().unwrap_or_default();
+  if !code.contains(css_images.function_name()) && !code.contains(css_images.marker_property()) {
+    return Ok(None);
+  }
+
+  let instant = Instant::now();
+
+  if let Some(log_level) = options.log_level {
+    set_log_level(log_level);
+  }
+
+  let mut found = vec![];
+  let mut input = ParserInput::new(&code);
+  find_images(&mut Parser::new(&mut input), &css_images, false, &mut found);
+  if found.is_empty() {
+    return Ok(None);
+  }
+
+  let aliases = options.alias.as_ref();
+  let global_options = PreviewOptions::from_global_options(&options);
+  let mut images = ImageQueue::new(&options, &file_path);
+
+  let mut resolved = vec![];
+  for image in found {
+    // Already inlined images of marked custom properties
+    if image.specifier.starts_with("data:") {
+      continue;
+    }
+
+    let mut preview_options = global_options.clone();
+    if let Some(value) = &image.placeholder_type {
+      let kind = PlaceholderImageOutputKind::from_string(value);
+      if kind.get_string_name() == *value {
+        preview_options.output_kind = kind;
+      } else {
+        create_log(
+          log::style_error(format!(
+            "Unknown placeholder type \"{}\" in {}, the default is used",
+            value, file_path
+          )),
+          LogLevel::Error,
+        );
+      }
+    }
+
+    let url = resolve_image_specifier(&image.specifier, &file_path, aliases);
+    images.queue_image(url.clone(), preview_options.clone(), false)?;
+    resolved.push((image.span, url, preview_options));
+  }
+  images.finish().await;
+
+  let mut patcher = SourcePatcher::new();
+  for (span, url, preview_options) in resolved {
+    if let Ok(placeholder) = images
+      .store
+      .get_placeholder_from_url_and_options(url, &preview_options)
+    {
+      patcher.replace(span, format!("url(\"{}\")", placeholder));
+    }
+  }
+  if patcher.is_empty() {
+    return Ok(None);
+  }
+
+  let sourcemap_file_path = options
+    .sourcemap_file_path
+    .clone()
+    .unwrap_or_else(|| file_path.clone());
+  let (result_code, map) = patcher.apply(&code, &sourcemap_file_path);
+  let map = compose_input_sourcemap(map, options.input_sourcemap.as_deref(), &file_path);
+
+  create_log(
+    log::style_info(format!(
+      "Finished processing file {} in {:?}",
+      file_path,
+      instant.elapsed()
+    )),
+    LogLevel::Verbose,
+  );
+
+  Ok(Some(TransformOutput {
+    code: result_code,
+    sourcemap: Some(map.to_json_string()),
+    logs: Some(log::collect_logs()),
+  }))
+}
+
+/// Collects the images of a block: the preview functions, and the `url()` values of marked custom properties.
+/// `marked` is set inside the value of a marked custom property.
+fn find_images(
+  parser: &mut Parser,
+  css_images: &CssImageOptions,
+  marked: bool,
+  images: &mut Vec<CssImage>,
+) {
+  let mut property = None;
+  let mut in_marked = marked;
+
+  loop {
+    let start = parser.position().byte_index() as u32;
+    let Ok(token) = parser.next_including_whitespace_and_comments().cloned() else {
+      break;
+    };
+
+    match token {
+      Token::Ident(name) => property = Some(name),
+      Token::Colon => {
+        in_marked |= property
+          .take()
+          .is_some_and(|name| name.starts_with(css_images.marker_property()));
+      }
+      Token::Semicolon => {
+        property = None;
+        in_marked = marked;
+      }
+      Token::WhiteSpace(_) | Token::Comment(_) => {}
+      Token::UnquotedUrl(url) if in_marked => {
+        images.push(CssImage {
+          span: Span::new(start, parser.position().byte_index() as u32),
+          specifier: url.to_string(),
+          placeholder_type: None,
+        });
+      }
+      Token::Function(name) if name.eq_ignore_ascii_case(css_images.function_name()) => {
+        let arguments = parser.parse_nested_block(parse_preview_arguments);
+        if let Ok((specifier, placeholder_type)) = arguments {
+          images.push(CssImage {
+            span: Span::new(start, parser.position().byte_index() as u32),
+            specifier,
+            placeholder_type,
+          });
+        }
+      }
+      Token::Function(name) if in_marked && name.eq_ignore_ascii_case("url") => {
+        let specifier = parser.parse_nested_block(|parser| {
+          Ok::<_, ParseError<()>>(parser.expect_string()?.to_string())
+        });
+        if let Ok(specifier) = specifier {
+          images.push(CssImage {
+            span: Span::new(start, parser.position().byte_index() as u32),
+            specifier,
+            placeholder_type: None,
+          });
+        }
+      }
+      Token::CurlyBracketBlock => {
+        let _ = parser.parse_nested_block(|parser| {
+          find_images(parser, css_images, false, images);
+          Ok::<_, ParseError<()>>(())
+        });
+        property = None;
+        in_marked = marked;
+      }
+      // Eg: `image-set(nocojs-preview("/hero.jpg") 1x)` or `var(--hero, nocojs-preview("/hero.jpg"))`
+      Token::Function(_) | Token::ParenthesisBlock | Token::SquareBracketBlock => {
+        let _ = parser.parse_nested_block(|parser| {
+          find_images(parser, css_images, in_marked, images);
+          Ok::<_, ParseError<()>>(())
+        });
+        property = None;
+      }
+      _ => property = None,
+    }
+  }
+}
+
+/// Parses the arguments of a preview function: the image, as a string or a `url()`,
+/// and an optional placeholder type, eg: `"/hero.jpg", blurred`.
+fn parse_preview_arguments<'i>(
+  parser: &mut Parser<'i, '_>,
+) -> Result<(String, Option<String>), ParseError<'i, ()>> {
+  let specifier = parser.expect_url_or_string()?.to_string();
+  let placeholder_type = parser
+    .try_parse(|parser| {
+      parser.expect_comma()?;
+      parser.expect_ident().map(|ident| ident.to_string())
+    })
+    .ok();
+  Ok((specifier, placeholder_type))
+}
@@ -0,0 +1,82 @@
+use std::{collections::HashSet, str::FromStr};
+
+use oxc::ast::Comment;
+
+use crate::{placeholder_image::PlaceholderImageOutputKind, transform::TransformOptions};
+
+/// Comment skipping the preview calls that follow it, eg: `/* @nocojs-ignore */ preview('/a.jpg')`.
+const IGNORE_DIRECTIVE: &str = "@nocojs-ignore";
+
+/// Comment overriding the transform options for a file, eg: `// @nocojs placeholderType=blurred width=24`.
+const PRAGMA_DIRECTIVE: &str = "@nocojs";
+
+/// Returns the start of the code following each `@nocojs-ignore` comment,
+/// eg: the start of the statement after `// @nocojs-ignore`, or of the call after `/* @nocojs-ignore */`.
+/// Trailing comments, eg: `preview('/a.jpg'); // @nocojs-ignore`, aren't attached to any code and are skipped.
+pub fn get_ignored_positions(comments: &[Comment], source_text: &str) -> HashSet<u32> {
+  comments
+    .iter()
+    .filter(|comment| comment.is_leading())
+    .filter(|comment| comment.content_span().source_text(source_text).trim() == IGNORE_DIRECTIVE)
+    .map(|comment| comment.attached_to)
+    .collect()
+}
+
+/// Returns the bodies of the `@nocojs` pragmas in the file header, the comments before `code_start`.
+/// Eg: `placeholderType=blurred width=24` for `// @nocojs placeholderType=blurred width=24`.
+pub fn get_file_pragmas<'s>(
+  comments: &[Comment],
+  source_text: &'s str,
+  code_start: u32,
+) -> Vec<&'s str> {
+  comments
+    .iter()
+    .take_while(|comment| comment.span.end <= code_start)
+    .filter_map(|comment| {
+      let content = comment.content_span().source_text(source_text).trim();
+      let body = content.strip_prefix(PRAGMA_DIRECTIVE)?;
+      (body.is_empty() || body.starts_with(char::is_whitespace)).then_some(body.trim())
+    })
+    .collect()
+}
+
+/// Applies the `key=value` pairs of a pragma on top of the transform options.
+/// Keys are named like the options of the JavaScript API, eg: `placeholderType` or `wrapWithSvg`.
+pub fn apply_pragma(options: &mut TransformOptions, pragma: &str) -> Result<(), String> {
+  for pair in pragma.split_whitespace() {
+    let (key, value) = pair
+      .split_once('=')
+      .ok_or_else(|| format!("Expected key=value, found \"{}\"", pair))?;
+
+    match key {
+      "placeholderType" => {
+        let kind = PlaceholderImageOutputKind::from_string(value);
+        if kind.get_string_name() != value {
+          return Err(format!("Unknown placeholder type \"{}\"", value));
+        }
+        options.placeholder_type = Some(kind);
+      }
+      "replaceFunctionCall" => options.replace_function_call = Some(parse_value(key, value)?),
+      "cache" => options.cache = Some(parse_value(key, value)?),
+      "width" => options.width = Some(parse_value(key, value)?),
+      "height" => options.height = Some(parse_value(key, value)?),
+      "wrapWithSvg" => options.wrap_with_svg = Some(parse_value(key, value)?),
+      "trim" => options.trim = Some(parse_value(key, value)?),
+      "trimTolerance" => options.trim_tolerance = Some(parse_value(key, value)?),
+      "targetArea" => options.target_area = Some(parse_value(key, value)?),
+      "minSide" => options.min_side = Some(parse_value(key, value)?),
+      "maxSide" => options.max_side = Some(parse_value(key, value)?),
+      "maxBytes" => options.max_bytes = Some(parse_value(key, value)?),
+      "targetSimilarity" => options.target_similarity = Some(parse_value(key, value)?),
+      _ => return Err(format!("Unknown option \"{}\"", key)),
+    }
+  }
+
+  Ok(())
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+  value
+    .parse()
+    .map_err(|_| format!("Invalid value \"{}\" for \"{}\"", value, key))
+}
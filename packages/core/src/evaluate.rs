@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use oxc::{
+  ast::ast::{
+    ArrayExpression, ArrayExpressionElement, BinaryExpression, BinaryOperator, Expression,
+    ObjectExpression, ObjectPropertyKind, PropertyKind, TemplateLiteral,
+  },
+  semantic::{Scoping, SymbolId},
+  span::{GetSpan, Span},
+};
+
+/// A value known at build time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+  String(String),
+  Number(f64),
+  Boolean(bool),
+  Object(ConstObject),
+  Array(Vec<ConstValue>),
+}
+
+/// Properties of an object known at build time, in the order they were defined.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConstObject(Vec<(String, ConstValue)>);
+
+impl ConstObject {
+  /// Sets a property. Like in JavaScript, an existing property keeps its position.
+  pub fn insert(&mut self, key: String, value: ConstValue) {
+    match self.0.iter_mut().find(|(existing, _)| *existing == key) {
+      Some((_, existing_value)) => *existing_value = value,
+      None => self.0.push((key, value)),
+    }
+  }
+
+  pub fn get(&self, key: &str) -> Option<&ConstValue> {
+    self
+      .0
+      .iter()
+      .find(|(existing, _)| existing == key)
+      .map(|(_, value)| value)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &(String, ConstValue)> {
+    self.0.iter()
+  }
+
+  /// Copies the properties of `other`, as a spread (`{ ...other }`) would.
+  pub fn extend(&mut self, other: ConstObject) {
+    for (key, value) in other.0 {
+      self.insert(key, value);
+    }
+  }
+}
+
+impl ConstValue {
+  /// Converts a primitive to a string the same way JavaScript does when concatenating.
+  pub fn to_js_string(&self) -> Option<String> {
+    match self {
+      ConstValue::String(value) => Some(value.clone()),
+      ConstValue::Number(value) => Some(number_to_js_string(*value)),
+      ConstValue::Boolean(value) => Some(value.to_string()),
+      ConstValue::Object(_) | ConstValue::Array(_) => None,
+    }
+  }
+}
+
+fn number_to_js_string(value: f64) -> String {
+  if value.fract() == 0.0 && value.abs() < 1e21 {
+    format!("{}", value as i64)
+  } else {
+    value.to_string()
+  }
+}
+
+/// Resolves expressions to values known at build time.
+/// Identifiers are resolved through the `const` bindings declared so far, using the semantic
+/// scoping to make sure a reference points at the declared binding and not at a shadowing one.
+/// Globals and member expressions such as `process.env.CDN_URL` are resolved through `defines`.
+pub struct ConstEvaluator<'s> {
+  scoping: &'s Scoping,
+  constants: HashMap<SymbolId, ConstValue>,
+  defines: HashMap<String, String>,
+}
+
+/// Result of evaluating an expression.
+/// The error holds the span of the sub-expression that couldn't be resolved.
+pub type EvaluateResult = Result<ConstValue, Span>;
+
+impl<'s> ConstEvaluator<'s> {
+  pub fn new(scoping: &'s Scoping, defines: HashMap<String, String>) -> Self {
+    ConstEvaluator {
+      scoping,
+      constants: HashMap::new(),
+      defines,
+    }
+  }
+
+  /// Records the value of a `const` binding.
+  pub fn declare(&mut self, symbol_id: SymbolId, value: ConstValue) {
+    self.constants.insert(symbol_id, value);
+  }
+
+  pub fn evaluate(&self, expr: &Expression) -> EvaluateResult {
+    match expr {
+      Expression::StringLiteral(literal) => Ok(ConstValue::String(literal.value.to_string())),
+      Expression::NumericLiteral(literal) => Ok(ConstValue::Number(literal.value)),
+      Expression::BooleanLiteral(literal) => Ok(ConstValue::Boolean(literal.value)),
+      Expression::TemplateLiteral(template) => self.evaluate_template(template),
+      Expression::BinaryExpression(binary) => self.evaluate_binary(binary),
+      Expression::ObjectExpression(object) => {
+        let (properties, unresolved) = self.evaluate_object_properties(object);
+        match unresolved.first() {
+          Some(span) => Err(*span),
+          None => Ok(ConstValue::Object(properties)),
+        }
+      }
+      Expression::ArrayExpression(array) => self.evaluate_array(array),
+      Expression::Identifier(identifier) => match self
+        .scoping
+        .get_reference(identifier.reference_id())
+        .symbol_id()
+      {
+        Some(symbol_id) => self.constants.get(&symbol_id).cloned(),
+        None => self.get_define(expr),
+      }
+      .ok_or(identifier.span),
+      Expression::StaticMemberExpression(member) => match self.get_define(expr) {
+        Some(value) => Ok(value),
+        None => {
+          let object = self.evaluate(&member.object)?;
+          get_property(&object, &member.property.name).ok_or(member.span)
+        }
+      },
+      Expression::ComputedMemberExpression(member) => match self.get_define(expr) {
+        Some(value) => Ok(value),
+        None => {
+          let object = self.evaluate(&member.object)?;
+          let key = self.evaluate(&member.expression)?;
+          let key = key.to_js_string().ok_or(member.expression.span())?;
+          get_property(&object, &key).ok_or(member.span)
+        }
+      },
+      Expression::MetaProperty(_) => self.get_define(expr).ok_or(expr.span()),
+      Expression::ParenthesizedExpression(parenthesized) => {
+        self.evaluate(&parenthesized.expression)
+      }
+      // Eg: const OPTIONS = { width: 24 } as const;
+      Expression::TSAsExpression(as_expr) => self.evaluate(&as_expr.expression),
+      Expression::TSSatisfiesExpression(satisfies) => self.evaluate(&satisfies.expression),
+      _ => Err(expr.span()),
+    }
+  }
+
+  /// Looks up a global identifier or member expression in the defines.
+  fn get_define(&self, expr: &Expression) -> Option<ConstValue> {
+    let key = self.get_define_key(expr)?;
+    self.defines.get(&key).cloned().map(ConstValue::String)
+  }
+
+  /// Builds the dotted path of an expression, eg: `process.env.CDN_URL` or `import.meta.env.BASE_URL`.
+  /// `process.env["CDN_URL"]` has the same path as `process.env.CDN_URL`.
+  /// Paths starting at a local binding (eg: a parameter named `process`) have no key.
+  fn get_define_key(&self, expr: &Expression) -> Option<String> {
+    match expr {
+      Expression::Identifier(identifier) => {
+        let reference = self.scoping.get_reference(identifier.reference_id());
+        match reference.symbol_id() {
+          Some(_) => None,
+          None => Some(identifier.name.to_string()),
+        }
+      }
+      Expression::MetaProperty(meta) => Some(format!("{}.{}", meta.meta.name, meta.property.name)),
+      Expression::StaticMemberExpression(member) => Some(format!(
+        "{}.{}",
+        self.get_define_key(&member.object)?,
+        member.property.name
+      )),
+      Expression::ComputedMemberExpression(member) => match &member.expression {
+        Expression::StringLiteral(property) => Some(format!(
+          "{}.{}",
+          self.get_define_key(&member.object)?,
+          property.value
+        )),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  /// Evaluates the properties of an object literal one by one, merging spreads of objects.
+  /// Returns the resolved properties and the spans of the ones that couldn't be resolved,
+  /// so that callers can use what is known and report the rest.
+  pub fn evaluate_object_properties(&self, object: &ObjectExpression) -> (ConstObject, Vec<Span>) {
+    let mut properties = ConstObject::default();
+    let mut unresolved = vec![];
+
+    for property in object.properties.iter() {
+      match property {
+        ObjectPropertyKind::ObjectProperty(key_value) => {
+          if key_value.kind != PropertyKind::Init || key_value.method {
+            unresolved.push(key_value.span);
+            continue;
+          }
+          let Some(key) = key_value.key.static_name() else {
+            unresolved.push(key_value.key.span());
+            continue;
+          };
+          match self.evaluate(&key_value.value) {
+            Ok(value) => {
+              properties.insert(key.to_string(), value);
+            }
+            Err(span) => unresolved.push(span),
+          }
+        }
+        ObjectPropertyKind::SpreadProperty(spread) => match self.evaluate(&spread.argument) {
+          Ok(ConstValue::Object(spread_properties)) => properties.extend(spread_properties),
+          Ok(_) => unresolved.push(spread.span),
+          Err(span) => unresolved.push(span),
+        },
+      }
+    }
+
+    (properties, unresolved)
+  }
+
+  fn evaluate_array(&self, array: &ArrayExpression) -> EvaluateResult {
+    let mut items = vec![];
+
+    for element in array.elements.iter() {
+      match element {
+        ArrayExpressionElement::SpreadElement(spread) => match self.evaluate(&spread.argument)? {
+          ConstValue::Array(spread_items) => items.extend(spread_items),
+          _ => return Err(spread.span),
+        },
+        ArrayExpressionElement::Elision(elision) => return Err(elision.span),
+        _ => items.push(self.evaluate(element.to_expression())?),
+      }
+    }
+
+    Ok(ConstValue::Array(items))
+  }
+
+  fn evaluate_template(&self, template: &TemplateLiteral) -> EvaluateResult {
+    let mut value = String::new();
+
+    for (index, quasi) in template.quasis.iter().enumerate() {
+      value.push_str(quasi.value.cooked.as_ref().ok_or(quasi.span)?);
+
+      if let Some(expression) = template.expressions.get(index) {
+        let part = self.evaluate(expression)?;
+        value.push_str(&part.to_js_string().ok_or(expression.span())?);
+      }
+    }
+
+    Ok(ConstValue::String(value))
+  }
+
+  fn evaluate_binary(&self, binary: &BinaryExpression) -> EvaluateResult {
+    if binary.operator != BinaryOperator::Addition {
+      return Err(binary.span);
+    }
+
+    let left = self.evaluate(&binary.left)?;
+    let right = self.evaluate(&binary.right)?;
+
+    match (&left, &right) {
+      (ConstValue::Number(left), ConstValue::Number(right)) => Ok(ConstValue::Number(left + right)),
+      (ConstValue::String(_), _) | (_, ConstValue::String(_)) => {
+        let left = left.to_js_string().ok_or(binary.left.span())?;
+        let right = right.to_js_string().ok_or(binary.right.span())?;
+        Ok(ConstValue::String(left + &right))
+      }
+      _ => Err(binary.span),
+    }
+  }
+}
+
+/// Reads a property of an object, or an item (or the length) of an array.
+fn get_property(value: &ConstValue, key: &str) -> Option<ConstValue> {
+  match value {
+    ConstValue::Object(object) => object.get(key).cloned(),
+    ConstValue::Array(items) if key == "length" => Some(ConstValue::Number(items.len() as f64)),
+    ConstValue::Array(items) => items.get(key.parse::<usize>().ok()?).cloned(),
+    _ => None,
+  }
+}
@@ -10,8 +10,11 @@ use url::Url;
 
 use crate::{
   log::{self, collect_logs, create_log, style_error, LogLevel},
-  placeholder_image::{process_image, wrap_with_svg, PlaceholderImageOutputKind},
-  store::Store,
+  placeholder_image::{
+    process_image, source_dimensions, wrap_with_svg, CropRect, FlipDirection,
+    PlaceholderImageOutputKind, PlaceholderTuning, TrimBox, DEFAULT_TRIM_TOLERANCE,
+  },
+  store::{ImageRow, Store},
   transform::{init_cache_dir, setup_sqlite, PreviewOptions, RUSQLITE_FILE_NAME},
 };
 
@@ -23,6 +26,16 @@ pub struct GetPlaceholderOptions {
   pub cache_file_dir: Option<String>,
   pub cache: Option<bool>,
   pub wrap_with_svg: Option<bool>,
+  pub trim: Option<bool>,
+  pub trim_tolerance: Option<u32>,
+  pub crop: Option<CropRect>,
+  pub rotate: Option<u32>,
+  pub flip: Option<FlipDirection>,
+  pub target_area: Option<u32>,
+  pub min_side: Option<u32>,
+  pub max_side: Option<u32>,
+  pub max_bytes: Option<u32>,
+  pub target_similarity: Option<f64>,
 }
 
 #[napi(object)]
@@ -30,6 +43,12 @@ pub struct GetPlaceholderOutput {
   pub placeholder: String,
   pub logs: Vec<log::Log>,
   pub is_error: bool,
+  /// Region of the source image the placeholder covers, when borders were trimmed.
+  pub trim_box: Option<TrimBox>,
+  /// Parameters chosen to fit the placeholder in `max_bytes`, when a budget was given.
+  pub tuning: Option<PlaceholderTuning>,
+  /// SSIM of the placeholder against the original, when a target similarity was given.
+  pub similarity: Option<f64>,
 }
 
 static HTTP_CLIENT: Lazy<Client> = Lazy::new(|| {
@@ -74,6 +93,16 @@ pub async fn get_placeholder(
     cache: options.cache.unwrap_or(true),
     wrap_with_svg: options.wrap_with_svg.unwrap_or(true),
     replace_function_call: false,
+    trim: options.trim.unwrap_or(false),
+    trim_tolerance: options.trim_tolerance.unwrap_or(DEFAULT_TRIM_TOLERANCE),
+    crop: options.crop.clone(),
+    rotate: options.rotate,
+    flip: options.flip.clone(),
+    target_area: options.target_area,
+    min_side: options.min_side,
+    max_side: options.max_side,
+    max_bytes: options.max_bytes,
+    target_similarity: options.target_similarity,
   };
 
   let db_filepath = PathBuf::from(&cache_dir).join(RUSQLITE_FILE_NAME);
@@ -95,41 +124,59 @@ pub async fn get_placeholder(
 
   let _ = setup_sqlite(&conn);
 
-  if let Ok((placeholder, original_width, original_height)) =
-    check_cache(url.clone(), &preview_options, &conn)
-  {
+  if let Ok(row) = check_cache(url.clone(), &preview_options, &conn) {
     create_log(format!("Cache hit for {}", url), LogLevel::Info);
+    let trim_box = row.trim_box.and_then(|s| TrimBox::from_string(&s));
+    let (source_width, source_height) = source_dimensions(
+      row.original_width,
+      row.original_height,
+      &trim_box,
+      &preview_options,
+    );
 
     return Ok(GetPlaceholderOutput {
       placeholder: if preview_options.wrap_with_svg
         && preview_options.output_kind != PlaceholderImageOutputKind::Blurred
       {
-        wrap_with_svg(placeholder, original_width, original_height)
+        wrap_with_svg(row.placeholder, source_width, source_height)
       } else {
-        placeholder
+        row.placeholder
       },
       logs: collect_logs(),
       is_error: false,
+      trim_box,
+      tuning: row.tuning.and_then(|s| PlaceholderTuning::from_string(&s)),
+      similarity: row.similarity,
     });
   }
 
   let bytes = get_bytes(url.clone()).await?;
 
-  match process_image(&bytes, &url, &preview_options).await {
+  match process_image(&bytes, &url, &preview_options, false).await {
     Ok(out) => {
+      let (source_width, source_height) = source_dimensions(
+        out.original_width,
+        out.original_height,
+        &out.trim_box,
+        &preview_options,
+      );
+
       if !preview_options.cache {
         return Ok(GetPlaceholderOutput {
           placeholder: {
             if preview_options.wrap_with_svg
               && preview_options.output_kind != PlaceholderImageOutputKind::Blurred
             {
-              wrap_with_svg(out.base64_str, out.original_width, out.original_height)
+              wrap_with_svg(out.base64_str, source_width, source_height)
             } else {
               out.base64_str
             }
           },
           logs: collect_logs(),
           is_error: false,
+          trim_box: out.trim_box,
+          tuning: out.tuning,
+          similarity: out.similarity,
         });
       }
 
@@ -141,18 +188,28 @@ pub async fn get_placeholder(
         cache_key.clone(),
         out.original_width,
         out.original_height,
+        out.trim_box.as_ref().map(TrimBox::get_string_name),
+        out.tuning.as_ref().map(PlaceholderTuning::get_string_name),
+        out.similarity,
+        out.dominant_color.clone(),
+        out.format.clone(),
       );
 
       if let Some(conn) = &conn {
         conn.execute(
-        "INSERT INTO images (url, placeholder, preview_type, cache_key, original_width, original_height) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        "INSERT INTO images (url, placeholder, preview_type, cache_key, original_width, original_height, trim_box, tuning, similarity, dominant_color, format) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
         params![
           to_insert.0,
           to_insert.1,
           to_insert.2,
           to_insert.3,
           to_insert.4,
-          to_insert.5
+          to_insert.5,
+          to_insert.6,
+          to_insert.7,
+          to_insert.8,
+          to_insert.9,
+          to_insert.10
         ],
       )?;
       }
@@ -161,12 +218,15 @@ pub async fn get_placeholder(
         placeholder: if preview_options.wrap_with_svg
           && preview_options.output_kind != PlaceholderImageOutputKind::Blurred
         {
-          wrap_with_svg(out.base64_str, out.original_width, out.original_height)
+          wrap_with_svg(out.base64_str, source_width, source_height)
         } else {
           out.base64_str
         },
         logs: collect_logs(),
         is_error: false,
+        trim_box: out.trim_box,
+        tuning: out.tuning,
+        similarity: out.similarity,
       })
     }
     Err(e) => {
@@ -183,7 +243,7 @@ fn check_cache(
   url: String,
   preview_options: &PreviewOptions,
   connection: &Option<Connection>,
-) -> Result<(String, u32, u32), Box<dyn std::error::Error>> {
+) -> Result<ImageRow, Box<dyn std::error::Error>> {
   let conn = match connection {
     Some(conn) => conn,
     None => {
@@ -192,13 +252,23 @@ fn check_cache(
   };
 
   let cache_key = Store::create_cache_key(preview_options);
-  let sql = "SELECT id, placeholder, original_width, original_height FROM images WHERE url = ? AND cache_key = ?";
+  let sql = "SELECT id, url, placeholder, preview_type, cache_key, original_width, original_height, trim_box, tuning, similarity, dominant_color, format FROM images WHERE url = ? AND cache_key = ?";
   let params = params![url, cache_key];
   let result = conn.query_row(sql, params, |row| {
-    let placeholder: String = row.get(1)?;
-    let original_width: u32 = row.get(2)?;
-    let original_height: u32 = row.get(3)?;
-    Ok((placeholder, original_width, original_height))
+    Ok(ImageRow {
+      id: row.get(0)?,
+      url: row.get(1)?,
+      placeholder: row.get(2)?,
+      preview_type: row.get(3)?,
+      cache_key: row.get(4)?,
+      original_width: row.get(5)?,
+      original_height: row.get(6)?,
+      trim_box: row.get(7)?,
+      tuning: row.get(8)?,
+      similarity: row.get(9)?,
+      dominant_color: row.get(10)?,
+      format: row.get(11)?,
+    })
   })?;
 
   Ok(result)
@@ -0,0 +1,232 @@
+use lol_html::{
+  element, errors::AttributeNameError, html_content::Element, rewrite_str, RewriteStrSettings,
+};
+use napi_derive::napi;
+use std::{collections::HashMap, time::Instant};
+
+use crate::{
+  image_queue::ImageQueue,
+  log::{self, create_log, set_log_level, LogLevel},
+  resolve::resolve_image_specifier,
+  store::Store,
+  transform::{PreviewOptions, TransformOptions, TransformOutput},
+};
+
+const DEFAULT_HTML_MARKER_ATTRIBUTE: &str = "data-nocojs";
+
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct HtmlImageOptions {
+  /// `<img>` and `<source>` elements with this attribute are enriched, the attribute is then removed.
+  /// Defaults to "data-nocojs".
+  pub marker_attribute: Option<String>,
+  /// Where the placeholder goes. Defaults to "src", the original URL is moved to `data-src`.
+  /// With "style", the placeholder becomes the background image of `<img>` elements.
+  /// `<source>` elements can't have a background image, their `srcset` receives the placeholder either way.
+  #[napi(ts_type = "'src' | 'style'")]
+  pub placeholder_attribute: Option<String>,
+}
+
+/// Where the placeholder of a marked element goes, see [`HtmlImageOptions::placeholder_attribute`].
+#[derive(Clone, Copy, PartialEq)]
+enum HtmlPlaceholderTarget {
+  Src,
+  Style,
+}
+
+impl HtmlImageOptions {
+  fn marker_attribute(&self) -> &str {
+    self
+      .marker_attribute
+      .as_deref()
+      .unwrap_or(DEFAULT_HTML_MARKER_ATTRIBUTE)
+  }
+
+  fn placeholder_target(&self) -> Result<HtmlPlaceholderTarget, String> {
+    match self.placeholder_attribute.as_deref() {
+      None | Some("src") => Ok(HtmlPlaceholderTarget::Src),
+      Some("style") => Ok(HtmlPlaceholderTarget::Style),
+      Some(value) => Err(format!(
+        "Unsupported HTML placeholder attribute \"{}\", expected \"src\" or \"style\"",
+        value
+      )),
+    }
+  }
+}
+
+/// Enriches the marked `<img>` and `<source>` elements of an HTML document, eg:
+/// `<img src="/hero.jpg" data-nocojs>` becomes
+/// `<img src="data:..." data-src="/hero.jpg" width="1600" height="900">`.
+/// For `<source srcset="...">` elements, the placeholder replaces the `srcset`, moved to `data-srcset`.
+/// Existing `width` and `height` attributes are kept.
+///
+/// Like `transform`, the images are first queued, then the document is rewritten once they're processed.
+pub async fn transform_html(
+  code: String,
+  file_path: String,
+  options: TransformOptions,
+) -> Result<Option<TransformOutput>, Box<dyn std::error::Error>> {
+  let html_images = options.html_images.clone().unwrap_or_default();
+  if !code.contains(html_images.marker_attribute()) {
+    return Ok(None);
+  }
+
+  let instant = Instant::now();
+
+  if let Some(log_level) = options.log_level {
+    set_log_level(log_level);
+  }
+
+  let placeholder_target = html_images.placeholder_target().inspect_err(|message| {
+    create_log(
+      log::style_error(format!("{}. File: {}", message, file_path)),
+      LogLevel::Error,
+    );
+  })?;
+
+  let aliases = options.alias.as_ref();
+  let preview_options = PreviewOptions::from_global_options(&options);
+  let mut images = ImageQueue::new(&options, &file_path);
+
+  let mut urls = vec![];
+  rewrite_str(
+    &code,
+    RewriteStrSettings {
+      element_content_handlers: vec![element!("img, source", |el| {
+        if let Some((attribute, value)) = get_image_source(el, html_images.marker_attribute()) {
+          urls.extend(get_image_url(attribute, &value, &file_path, aliases));
+        }
+        Ok(())
+      })],
+      ..RewriteStrSettings::new()
+    },
+  )?;
+
+  if urls.is_empty() {
+    return Ok(None);
+  }
+  for url in urls {
+    images.queue_image(url, preview_options.clone(), true)?;
+  }
+  images.finish().await;
+
+  let result_code = rewrite_str(
+    &code,
+    RewriteStrSettings {
+      element_content_handlers: vec![element!("img, source", |el| {
+        enrich_html_image(
+          el,
+          &html_images,
+          placeholder_target,
+          &images.store,
+          &preview_options,
+          &file_path,
+          aliases,
+        )?;
+        Ok(())
+      })],
+      ..RewriteStrSettings::new()
+    },
+  )?;
+
+  create_log(
+    log::style_info(format!(
+      "Finished processing file {} in {:?}",
+      file_path,
+      instant.elapsed()
+    )),
+    LogLevel::Verbose,
+  );
+
+  Ok(Some(TransformOutput {
+    code: result_code,
+    sourcemap: None,
+    logs: Some(log::collect_logs()),
+  }))
+}
+
+/// Returns the attribute holding the image of a marked element, with its raw value:
+/// `src`, or `srcset` for `<source>` elements without `src`.
+fn get_image_source(element: &Element, marker_attribute: &str) -> Option<(&'static str, String)> {
+  if !element.has_attribute(marker_attribute) {
+    return None;
+  }
+
+  let attribute = if element.tag_name() == "source" && !element.has_attribute("src") {
+    "srcset"
+  } else {
+    "src"
+  };
+  let value = element.get_attribute(attribute)?;
+  Some((attribute, value))
+}
+
+/// Resolves the image of a `src` or `srcset` attribute, the first candidate of a `srcset`,
+/// eg: `/hero.jpg` for `/hero.jpg 1x, /hero@2x.jpg 2x`.
+pub(crate) fn get_image_url(
+  attribute: &str,
+  value: &str,
+  file_path: &str,
+  aliases: Option<&HashMap<String, String>>,
+) -> Option<String> {
+  let url = match attribute {
+    "srcset" => value.split(',').next()?.split_whitespace().next()?,
+    _ => Some(value.trim()).filter(|value| !value.is_empty())?,
+  };
+  // Values are read with their entities, eg: `&amp;` in query strings
+  let url = url.replace("&amp;", "&");
+  Some(resolve_image_specifier(&url, file_path, aliases))
+}
+
+/// Fills in the placeholder and the dimensions of a marked element, and moves its image to a `data-` attribute.
+fn enrich_html_image(
+  element: &mut Element,
+  html_images: &HtmlImageOptions,
+  placeholder_target: HtmlPlaceholderTarget,
+  store: &Store,
+  options: &PreviewOptions,
+  file_path: &str,
+  aliases: Option<&HashMap<String, String>>,
+) -> Result<(), AttributeNameError> {
+  let Some((attribute, value)) = get_image_source(element, html_images.marker_attribute()) else {
+    return Ok(());
+  };
+  let Some(meta) = get_image_url(attribute, &value, file_path, aliases)
+    .and_then(|url| store.get_meta_from_url_and_options(url, options).ok())
+  else {
+    return Ok(());
+  };
+
+  element.set_attribute(&format!("data-{}", attribute), &value)?;
+  if placeholder_target == HtmlPlaceholderTarget::Style && element.tag_name() == "img" {
+    element.remove_attribute(attribute);
+    let style = add_background_placeholder(element.get_attribute("style"), &meta.placeholder);
+    element.set_attribute("style", &style)?;
+  } else {
+    element.set_attribute(attribute, &meta.placeholder)?;
+  }
+
+  for (name, value) in [("width", meta.width), ("height", meta.height)] {
+    if !element.has_attribute(name) {
+      element.set_attribute(name, &value.to_string())?;
+    }
+  }
+
+  // The marker is a build-time directive only
+  element.remove_attribute(html_images.marker_attribute());
+  Ok(())
+}
+
+/// Appends the placeholder as a background image to the `style` of an element.
+pub(crate) fn add_background_placeholder(style: Option<String>, placeholder: &str) -> String {
+  let background = format!(
+    "background-image: url(\"{}\"); background-size: cover",
+    placeholder
+  );
+  match style {
+    Some(style) if !style.trim().is_empty() => {
+      format!("{}; {}", style.trim_end().trim_end_matches(';'), background)
+    }
+    _ => background,
+  }
+}
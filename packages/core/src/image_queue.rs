@@ -0,0 +1,439 @@
+use bytes::Bytes;
+use futures::Future;
+#[cfg(not(target_arch = "wasm32"))]
+use futures::{stream::FuturesUnordered, StreamExt};
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use rusqlite::Connection;
+#[cfg(target_arch = "wasm32")]
+use std::mem;
+#[cfg(target_arch = "wasm32")]
+use std::pin::Pin;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+use std::{
+  collections::HashMap,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::task::JoinHandle;
+use url::Url;
+
+use crate::{
+  log::{self, create_log, style_error, LogLevel},
+  placeholder_image::{download_and_process_image, process_image},
+  resolve::find_local_image,
+  store::{ImageRow, Store},
+  transform::{init_cache_dir, setup_sqlite, PreviewOptions, TransformOptions, RUSQLITE_FILE_NAME},
+};
+
+static HTTP_CLIENT: Lazy<Client> = Lazy::new(|| {
+  #[cfg(not(target_arch = "wasm32"))]
+  let mut builder = Client::builder();
+
+  #[cfg(target_arch = "wasm32")]
+  let builder = Client::builder();
+
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    builder = builder.timeout(Duration::from_secs(10));
+  }
+  // In CI or testing environments, be more permissive with certificates
+  #[cfg(not(target_arch = "wasm32"))]
+  if std::env::var("NOCOJS_DANGEROUSLY_ACCEPT_INVALID_SSL_CERTS").is_ok() {
+    builder = builder.danger_accept_invalid_certs(true);
+  }
+
+  builder.build().unwrap()
+});
+
+#[cfg(target_arch = "wasm32")]
+fn spawn_task<F>(fut: F) -> std::pin::Pin<Box<dyn Future<Output = ()> + 'static>>
+where
+  F: Future<Output = ()> + 'static,
+{
+  Box::pin(fut)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn_task<F>(fut: F) -> tokio::task::JoinHandle<()>
+where
+  F: Future<Output = ()> + Send + 'static,
+{
+  tokio::spawn(fut)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type TasksType<T> = FuturesUnordered<JoinHandle<T>>;
+
+#[cfg(target_arch = "wasm32")]
+type TasksType<T> = Vec<Pin<Box<dyn Future<Output = T>>>>;
+
+/// Processes the images used by a file, eg: the images of the `preview()` calls of a module
+/// or of the marked `<img>` elements of an HTML document.
+/// Images are looked up in the persistent cache first, the others are processed asynchronously
+/// and saved to the cache once every image is processed.
+pub struct ImageQueue {
+  rusqlite_conn: Option<Connection>,
+  tasks: TasksType<()>,
+  pub store: Arc<Store>,
+  file_path: String,
+  /// Directories the paths starting with `/` are looked up in, eg: `public`.
+  public_dirs: Vec<String>,
+  /// Every image queued, with whether its metadata is used.
+  pub queued_images: Vec<(String, PreviewOptions, bool)>,
+}
+
+impl ImageQueue {
+  /// Opens the cache database of `options.cache_file_dir` and loads the cached images.
+  /// When the database can't be opened, images are only cached for this file.
+  pub fn new(options: &TransformOptions, file_path: &str) -> ImageQueue {
+    let cache_dir = init_cache_dir(
+      &options
+        .cache_file_dir
+        .clone()
+        .unwrap_or(".nocojs".to_string()),
+    )
+    .unwrap_or("".to_string());
+
+    let db_filepath = PathBuf::from(&cache_dir).join(RUSQLITE_FILE_NAME);
+    let conn = Connection::open(&db_filepath);
+
+    let conn = match conn {
+      Ok(c) => Some(c),
+      Err(e) => {
+        create_log(
+          log::style_error(format!(
+            "Failed to open sqlite database at {:?}. Persistent caching won't work. Error: {}",
+            db_filepath, e
+          )),
+          LogLevel::Error,
+        );
+        None
+      }
+    };
+
+    let _ = setup_sqlite(&conn);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let tasks = FuturesUnordered::new();
+
+    #[cfg(target_arch = "wasm32")]
+    let tasks = vec![];
+
+    let mut queue = ImageQueue {
+      rusqlite_conn: conn,
+      tasks,
+      store: Arc::new(Store {
+        data: Arc::new(Mutex::new(HashMap::new())),
+      }),
+      file_path: file_path.to_string(),
+      public_dirs: options
+        .public_dir
+        .iter()
+        .chain(options.public_dirs.iter().flatten())
+        .cloned()
+        .collect(),
+      queued_images: vec![],
+    };
+    let _ = queue.set_store_data_from_db();
+    queue
+  }
+
+  /// Waits for the queued images to be processed and saves them to the cache.
+  #[allow(clippy::redundant_pattern_matching)]
+  pub async fn finish(&mut self) {
+    #[cfg(target_arch = "wasm32")]
+    {
+      let tasks = mem::take(&mut self.tasks); // replace with empty Vec
+
+      for task in tasks {
+        let _ = task.await;
+      }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    while let Some(_) = self.tasks.next().await {}
+
+    let _ = self.push_store_data_to_db();
+  }
+
+  /// Reads the existing data from the database and populates the store.
+  /// This is called when the queue is created to ensure that any existing cached images
+  /// are available for the transformation process.
+  fn set_store_data_from_db(&mut self) -> Result<(), Box<dyn std::error::Error + '_>> {
+    let conn = match &self.rusqlite_conn {
+      Some(c) => c,
+      None => {
+        return Ok(());
+      }
+    };
+
+    let mut stmt = conn.prepare("SELECT id, url, placeholder, preview_type, cache_key, original_width, original_height, trim_box, tuning, similarity, dominant_color, format FROM images")?;
+
+    let rows = stmt.query_map([], |row| {
+      Ok(ImageRow {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        placeholder: row.get(2)?,
+        preview_type: row.get(3)?,
+        cache_key: row.get(4)?,
+        original_width: row.get(5)?,
+        original_height: row.get(6)?,
+        trim_box: row.get(7)?,
+        tuning: row.get(8)?,
+        similarity: row.get(9)?,
+        dominant_color: row.get(10)?,
+        format: row.get(11)?,
+      })
+    })?;
+
+    let mut to_insert = vec![];
+    for row in rows {
+      to_insert.push(self.store.create_item_from_row(row?)?);
+    }
+
+    if !to_insert.is_empty() {
+      create_log(
+        log::style_info(format!(
+          "Loaded {} cached images from the database",
+          to_insert.len()
+        )),
+        LogLevel::Verbose,
+      );
+    }
+
+    self.store.bulk_insert(to_insert)?;
+
+    Ok(())
+  }
+
+  /// Spawns a task to process the image, unless it is already in the cache.
+  /// `require_meta` reprocesses images cached without the metadata used by `previewMeta()`.
+  pub fn queue_image(
+    &mut self,
+    url: String,
+    options: PreviewOptions,
+    require_meta: bool,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    self
+      .queued_images
+      .push((url.clone(), options.clone(), require_meta));
+
+    let exists_in_cache = {
+      self
+        .store
+        .has_cached_image(url.clone(), &options, require_meta)?
+    };
+
+    if exists_in_cache {
+      create_log(
+        log::style_info(format!("Cache hit for {}", url)),
+        LogLevel::Verbose,
+      );
+      return Ok(());
+    }
+
+    self.spawn_image_resize(url, options, require_meta);
+    Ok(())
+  }
+
+  /// Pushes the current state of the store to the database.
+  /// It prepares the data for insertion and/or update based on the current state of the store.
+  /// Function called once the queued images are processed.
+  fn push_store_data_to_db(&mut self) -> Result<(), Box<dyn std::error::Error + '_>> {
+    if !self.store.has_changes()? {
+      return Ok(());
+    }
+
+    let conn = match &mut self.rusqlite_conn {
+      Some(c) => c,
+      None => {
+        return Ok(());
+      }
+    };
+
+    let (to_insert, to_update) = self.store.get_prepared_data()?;
+
+    let tx = conn.transaction()?;
+    {
+      let mut insert_query = tx.prepare(
+        "INSERT INTO images (url, placeholder, preview_type, cache_key, original_width, original_height, trim_box, tuning, similarity, dominant_color, format) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+      )?;
+
+      if !to_insert.is_empty() {
+        create_log(
+          log::style_info(format!(
+            "Inserting {} new images into the database",
+            to_insert.len()
+          )),
+          LogLevel::Verbose,
+        );
+      }
+
+      for row in to_insert {
+        insert_query.execute((
+          row.url,
+          row.placeholder,
+          row.preview_type,
+          row.cache_key,
+          row.original_width,
+          row.original_height,
+          row.trim_box,
+          row.tuning,
+          row.similarity,
+          row.dominant_color,
+          row.format,
+        ))?;
+      }
+
+      let mut update_query = tx.prepare(
+        "UPDATE images SET placeholder = ?, preview_type = ?, cache_key = ?, original_width = ?, original_height = ?, trim_box = ?, tuning = ?, similarity = ?, dominant_color = ?, format = ? WHERE id = ?",
+      )?;
+
+      if !to_update.is_empty() {
+        create_log(
+          log::style_info(format!(
+            "Updating {} existing images in the database",
+            to_update.len()
+          )),
+          LogLevel::Verbose,
+        );
+      }
+
+      for row in to_update {
+        update_query.execute((
+          row.placeholder,
+          row.preview_type,
+          row.cache_key,
+          row.original_width,
+          row.original_height,
+          row.trim_box,
+          row.tuning,
+          row.similarity,
+          row.dominant_color,
+          row.format,
+          row.id,
+        ))?;
+      }
+    }
+
+    tx.commit()?;
+    Ok(())
+  }
+
+  /// Spawns a task to process the image asynchronously.
+  /// If the URL is an actual URL, it downloads the image and processes it.
+  /// If the URL is a `file://` URL (eg: an image outside the working directory), it reads the image from disk.
+  /// Other paths are read from the working directory for the keys of local images, eg: `./src/a.jpg`,
+  /// from the public directories, or from `node_modules` for package paths.
+  /// The processed image output is then inserted or updated in the store.
+  /// `require_meta` also computes the metadata used by `previewMeta()`, eg: the dominant colour.
+  fn spawn_image_resize(&mut self, url: String, options: PreviewOptions, require_meta: bool) {
+    let url_parse = Url::parse(&url);
+
+    if let Some(file_url) = url_parse
+      .as_ref()
+      .ok()
+      .filter(|parsed| parsed.scheme() == "file")
+    {
+      let Ok(image_path) = file_url.to_file_path() else {
+        create_log(
+          format!(
+            "Invalid file URL: {}. Image used in file: {}",
+            url, self.file_path
+          ),
+          LogLevel::Error,
+        );
+        return;
+      };
+
+      if image_path.exists() {
+        self.spawn_local_image_resize(url, image_path, options, require_meta);
+      } else {
+        create_log(
+          format!(
+            "Image not found: {:?}. Image used in file: {}",
+            image_path, self.file_path
+          ),
+          LogLevel::Error,
+        );
+      }
+    } else if url_parse.is_err() {
+      // Assumes the URL is a path in one of the public directories or a package path
+      let public_dirs = &self.public_dirs;
+      if let Some(image_path) = find_local_image(&url, public_dirs, &self.file_path) {
+        self.spawn_local_image_resize(url, image_path, options, require_meta);
+      } else {
+        create_log(
+          format!(
+            "Image not found in the public directories {:?} or node_modules: {}. Image used in file: {}",
+            public_dirs, url, self.file_path
+          ),
+          LogLevel::Error,
+        );
+      }
+    } else {
+      let client = &*HTTP_CLIENT;
+      let url_clone = url.clone();
+      let store = Arc::clone(&self.store);
+      let file_path_clone = self.file_path.clone();
+
+      self.tasks.push(spawn_task(async move {
+        match download_and_process_image(client, &url, &options, require_meta).await {
+          Ok(image) => {
+            let _ = store.insert_or_update(url_clone, image, &options);
+          }
+          Err(e) => create_log(
+            format!(
+              "Failed to process image {} in {}. Error: {}",
+              url_clone, file_path_clone, e
+            ),
+            LogLevel::Error,
+          ),
+        }
+      }));
+    }
+  }
+
+  /// Reads an image from disk and spawns a task to process it.
+  /// The output is stored under `url`.
+  fn spawn_local_image_resize(
+    &mut self,
+    url: String,
+    image_path: PathBuf,
+    options: PreviewOptions,
+    require_meta: bool,
+  ) {
+    let file_read = std::fs::read(image_path.as_path());
+    if file_read.is_err() {
+      create_log(
+        style_error(format!(
+          "Failed to read image: {:?}. File {}",
+          image_path, self.file_path
+        )),
+        LogLevel::Error,
+      );
+      return;
+    }
+    let bytes = Bytes::from(file_read.unwrap());
+    let store = Arc::clone(&self.store);
+    let file_path_clone = self.file_path.clone();
+
+    self.tasks.push(spawn_task(async move {
+      match process_image(&bytes, &url, &options, require_meta).await {
+        Ok(out) => {
+          let _ = store.insert_or_update(url, out, &options);
+        }
+        Err(e) => create_log(
+          format!(
+            "Failed to process image {} in {}. Error: {}",
+            url, file_path_clone, e
+          ),
+          LogLevel::Error,
+        ),
+      }
+    }));
+  }
+}
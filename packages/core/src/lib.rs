@@ -1,9 +1,20 @@
 #![deny(clippy::all)]
 #![allow(clippy::uninlined_format_args)]
 
+pub mod component;
+pub mod css;
+pub mod directives;
+pub mod evaluate;
 pub mod get_placeholder;
+pub mod html;
+pub mod image_queue;
 pub mod log;
+pub mod markdown;
 pub mod placeholder_image;
+pub mod resolve;
+pub mod similarity;
+pub mod source_patch;
+pub mod sourcemap;
 pub mod store;
 pub mod transform;
 
@@ -36,6 +47,24 @@ pub async fn transform(
       height: options.height,
       sourcemap_file_path: options.sourcemap_file_path,
       wrap_with_svg: options.wrap_with_svg,
+      trim: options.trim,
+      trim_tolerance: options.trim_tolerance,
+      target_area: options.target_area,
+      min_side: options.min_side,
+      max_side: options.max_side,
+      max_bytes: options.max_bytes,
+      target_similarity: options.target_similarity,
+      preserve_formatting: options.preserve_formatting,
+      input_sourcemap: options.input_sourcemap,
+      import_sources: options.import_sources,
+      define: options.define,
+      alias: options.alias,
+      public_dirs: options.public_dirs,
+      jsx_images: options.jsx_images,
+      next_image_blur: options.next_image_blur,
+      html_images: options.html_images,
+      css_images: options.css_images,
+      markdown_images: options.markdown_images,
     },
   )
   .await;
@@ -47,6 +76,61 @@ pub async fn transform(
   }))
 }
 
+/// Enriches the `<img>` and `<source>` elements of an HTML document marked with `html_images.marker_attribute`.
+#[cfg_attr(target_arch = "wasm32", napi::tokio::main(flavor = "current_thread"))]
+#[cfg_attr(not(target_arch = "wasm32"), napi::tokio::main)]
+#[napi]
+pub async fn transform_html(
+  code: String,
+  file_path: String,
+  options: TransformOptions,
+) -> Option<TransformOutput> {
+  let out = html::transform_html(code.clone(), file_path, options).await;
+
+  out.unwrap_or(Some(TransformOutput {
+    code,
+    sourcemap: None,
+    logs: Some(log::collect_logs()),
+  }))
+}
+
+/// Replaces the preview functions and the `url()` values of marked custom properties of a stylesheet.
+#[cfg_attr(target_arch = "wasm32", napi::tokio::main(flavor = "current_thread"))]
+#[cfg_attr(not(target_arch = "wasm32"), napi::tokio::main)]
+#[napi]
+pub async fn transform_css(
+  code: String,
+  file_path: String,
+  options: TransformOptions,
+) -> Option<TransformOutput> {
+  let out = css::transform_css(code.clone(), file_path, options).await;
+
+  out.unwrap_or(Some(TransformOutput {
+    code,
+    sourcemap: None,
+    logs: Some(log::collect_logs()),
+  }))
+}
+
+/// Enriches the `![alt](src)` images and the `<img>` elements of a Markdown document,
+/// or lists their placeholders in its front matter.
+#[cfg_attr(target_arch = "wasm32", napi::tokio::main(flavor = "current_thread"))]
+#[cfg_attr(not(target_arch = "wasm32"), napi::tokio::main)]
+#[napi]
+pub async fn transform_markdown(
+  code: String,
+  file_path: String,
+  options: TransformOptions,
+) -> Option<TransformOutput> {
+  let out = markdown::transform_markdown(code.clone(), file_path, options).await;
+
+  out.unwrap_or(Some(TransformOutput {
+    code,
+    sourcemap: None,
+    logs: Some(log::collect_logs()),
+  }))
+}
+
 #[cfg_attr(target_arch = "wasm32", napi::tokio::main(flavor = "current_thread"))]
 #[cfg_attr(not(target_arch = "wasm32"), napi::tokio::main)]
 #[napi]
@@ -60,6 +144,9 @@ pub async fn get_placeholder(
       placeholder: url,
       logs: log::collect_logs(),
       is_error: true,
+      trim_box: None,
+      tuning: None,
+      similarity: None,
     }),
   }
 }
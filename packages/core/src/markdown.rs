@@ -0,0 +1,434 @@
+use lol_html::{
+  element, errors::AttributeNameError, html_content::Element, rewrite_str, RewriteStrSettings,
+};
+use napi_derive::napi;
+use oxc::span::Span;
+use pulldown_cmark::{Event, MetadataBlockKind, Options, Parser, Tag, TagEnd};
+use std::{collections::HashMap, time::Instant};
+
+use crate::{
+  html::{add_background_placeholder, get_image_url},
+  image_queue::ImageQueue,
+  log::{self, create_log, set_log_level, LogLevel},
+  resolve::resolve_image_specifier,
+  source_patch::SourcePatcher,
+  sourcemap::compose_input_sourcemap,
+  store::{PreviewMeta, Store},
+  transform::{PreviewOptions, TransformOptions, TransformOutput},
+};
+
+const DEFAULT_MARKDOWN_OUTPUT: &str = "html";
+const DEFAULT_MARKDOWN_PLACEHOLDER_ATTRIBUTE: &str = "style";
+/// Key of the placeholder map added to the front matter.
+const FRONT_MATTER_KEY: &str = "nocojs";
+
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct MarkdownImageOptions {
+  /// "html" replaces the images with `<img>` elements holding their placeholder and dimensions,
+  /// "frontmatter" lists the placeholders in the front matter instead. Defaults to "html".
+  pub output: Option<String>,
+  /// Attribute of the `<img>` elements receiving the placeholder, or "style" for a background image.
+  /// Defaults to "style".
+  pub placeholder_attribute: Option<String>,
+}
+
+impl MarkdownImageOptions {
+  fn output(&self) -> &str {
+    self.output.as_deref().unwrap_or(DEFAULT_MARKDOWN_OUTPUT)
+  }
+
+  fn placeholder_attribute(&self) -> &str {
+    self
+      .placeholder_attribute
+      .as_deref()
+      .unwrap_or(DEFAULT_MARKDOWN_PLACEHOLDER_ATTRIBUTE)
+  }
+}
+
+/// An image of the document. `span` covers the `![alt](src)` syntax, or None for the `<img>` of HTML.
+struct MarkdownImage {
+  span: Option<Span>,
+  specifier: String,
+  url: String,
+  alt: String,
+  title: String,
+}
+
+/// The images of a document in order, the spans of its HTML, and the span and kind of its front matter.
+struct MarkdownDocument {
+  images: Vec<MarkdownImage>,
+  html_spans: Vec<Span>,
+  front_matter: Option<(Span, MetadataBlockKind)>,
+}
+
+/// Enriches the images of a Markdown document, both `![alt](src)` and the `<img>` elements of HTML, eg:
+/// `![Hero](/hero.jpg)` becomes
+/// `<img src="/hero.jpg" alt="Hero" width="1600" height="900" loading="lazy" style="background-image: ...">`.
+/// With the "frontmatter" output, the document is left as is and the placeholders are listed
+/// under the `nocojs` key of its front matter, by image.
+///
+/// Like `transform`, the images are first queued, then the document is patched once they're processed.
+pub async fn transform_markdown(
+  code: String,
+  file_path: String,
+  options: TransformOptions,
+) -> Result<Option<TransformOutput>, Box<dyn std::error::Error>> {
+  if !code.contains("![") && !code.contains("<img") {
+    return Ok(None);
+  }
+
+  let markdown_images = options.markdown_images.clone().unwrap_or_default();
+  let instant = Instant::now();
+
+  if let Some(log_level) = options.log_level {
+    set_log_level(log_level);
+  }
+
+  let aliases = options.alias.as_ref();
+  let MarkdownDocument {
+    images: found,
+    html_spans,
+    front_matter,
+  } = find_images(&code, &file_path, aliases)?;
+  if found.is_empty() {
+    return Ok(None);
+  }
+
+  let preview_options = PreviewOptions::from_global_options(&options);
+  let mut images = ImageQueue::new(&options, &file_path);
+  for image in &found {
+    images.queue_image(image.url.clone(), preview_options.clone(), true)?;
+  }
+  images.finish().await;
+
+  let mut patcher = SourcePatcher::new();
+  match markdown_images.output() {
+    "frontmatter" => {
+      let mut entries: Vec<(&str, PreviewMeta)> = vec![];
+      for image in &found {
+        if entries
+          .iter()
+          .any(|(specifier, _)| *specifier == image.specifier)
+        {
+          continue;
+        }
+        if let Ok(meta) = images
+          .store
+          .get_meta_from_url_and_options(image.url.clone(), &preview_options)
+        {
+          entries.push((&image.specifier, meta));
+        }
+      }
+      if !entries.is_empty() {
+        insert_front_matter(&mut patcher, &code, front_matter, &entries, &file_path);
+      }
+    }
+    _ => {
+      let placeholder_attribute = markdown_images.placeholder_attribute();
+      for image in &found {
+        let Some(span) = image.span else {
+          continue;
+        };
+        if let Ok(meta) = images
+          .store
+          .get_meta_from_url_and_options(image.url.clone(), &preview_options)
+        {
+          patcher.replace(span, render_image(image, &meta, placeholder_attribute));
+        }
+      }
+
+      for span in html_spans {
+        let fragment = span.source_text(&code);
+        let enriched = rewrite_str(
+          fragment,
+          RewriteStrSettings {
+            element_content_handlers: vec![element!("img[src]", |el| {
+              enrich_markdown_html_image(
+                el,
+                placeholder_attribute,
+                &images.store,
+                &preview_options,
+                &file_path,
+                aliases,
+              )?;
+              Ok(())
+            })],
+            ..RewriteStrSettings::new()
+          },
+        )?;
+        if enriched != fragment {
+          patcher.replace(span, enriched);
+        }
+      }
+    }
+  }
+
+  if patcher.is_empty() {
+    return Ok(None);
+  }
+
+  let sourcemap_file_path = options
+    .sourcemap_file_path
+    .clone()
+    .unwrap_or_else(|| file_path.clone());
+  let (result_code, map) = patcher.apply(&code, &sourcemap_file_path);
+  let map = compose_input_sourcemap(map, options.input_sourcemap.as_deref(), &file_path);
+
+  create_log(
+    log::style_info(format!(
+      "Finished processing file {} in {:?}",
+      file_path,
+      instant.elapsed()
+    )),
+    LogLevel::Verbose,
+  );
+
+  Ok(Some(TransformOutput {
+    code: result_code,
+    sourcemap: Some(map.to_json_string()),
+    logs: Some(log::collect_logs()),
+  }))
+}
+
+/// Reads the images, the HTML and the front matter of a document.
+fn find_images(
+  code: &str,
+  file_path: &str,
+  aliases: Option<&HashMap<String, String>>,
+) -> Result<MarkdownDocument, Box<dyn std::error::Error>> {
+  let mut images = vec![];
+  let mut html_spans = vec![];
+  let mut front_matter = None;
+  // Images being read, their alt text is the text of the events until their end
+  let mut current: Option<MarkdownImage> = None;
+  let mut depth = 0;
+
+  let parser = Parser::new_ext(
+    code,
+    Options::ENABLE_YAML_STYLE_METADATA_BLOCKS | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS,
+  );
+  for (event, range) in parser.into_offset_iter() {
+    let span = Span::new(range.start as u32, range.end as u32);
+    match event {
+      Event::Start(Tag::MetadataBlock(kind)) => front_matter = Some((span, kind)),
+      Event::Start(Tag::Image {
+        dest_url, title, ..
+      }) => {
+        depth += 1;
+        if current.is_some() || dest_url.starts_with("data:") {
+          continue;
+        }
+        current = Some(MarkdownImage {
+          span: Some(span),
+          specifier: dest_url.to_string(),
+          url: resolve_image_specifier(&dest_url, file_path, aliases),
+          alt: String::new(),
+          title: title.to_string(),
+        });
+      }
+      Event::End(TagEnd::Image) => {
+        depth -= 1;
+        if depth == 0 {
+          images.extend(current.take());
+        }
+      }
+      Event::Text(text) | Event::Code(text) => {
+        if let Some(image) = &mut current {
+          image.alt.push_str(&text);
+        }
+      }
+      Event::SoftBreak | Event::HardBreak => {
+        if let Some(image) = &mut current {
+          image.alt.push(' ');
+        }
+      }
+      // Whole HTML blocks, as their elements can span several `Html` events
+      Event::Start(Tag::HtmlBlock) | Event::InlineHtml(_) if current.is_none() => {
+        let specifiers = get_html_image_specifiers(span.source_text(code))?;
+        if specifiers.is_empty() {
+          continue;
+        }
+        html_spans.push(span);
+        images.extend(specifiers.into_iter().filter_map(|specifier| {
+          let url = get_image_url("src", &specifier, file_path, aliases)?;
+          Some(MarkdownImage {
+            span: None,
+            specifier: specifier.replace("&amp;", "&"),
+            url,
+            alt: String::new(),
+            title: String::new(),
+          })
+        }));
+      }
+      _ => {}
+    }
+  }
+
+  Ok(MarkdownDocument {
+    images,
+    html_spans,
+    front_matter,
+  })
+}
+
+/// Returns the raw `src` of the `<img>` elements of an HTML fragment.
+fn get_html_image_specifiers(fragment: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+  if !fragment.contains("<img") {
+    return Ok(vec![]);
+  }
+
+  let mut specifiers = vec![];
+  rewrite_str(
+    fragment,
+    RewriteStrSettings {
+      element_content_handlers: vec![element!("img[src]", |el| {
+        specifiers.extend(
+          el.get_attribute("src")
+            .filter(|src| !src.starts_with("data:")),
+        );
+        Ok(())
+      })],
+      ..RewriteStrSettings::new()
+    },
+  )?;
+  Ok(specifiers)
+}
+
+/// Renders a `![alt](src "title")` image as an `<img>` element with its placeholder and dimensions.
+fn render_image(image: &MarkdownImage, meta: &PreviewMeta, placeholder_attribute: &str) -> String {
+  let mut attributes = vec![("src", image.specifier.clone()), ("alt", image.alt.clone())];
+  if !image.title.is_empty() {
+    attributes.push(("title", image.title.clone()));
+  }
+  attributes.extend([
+    ("width", meta.width.to_string()),
+    ("height", meta.height.to_string()),
+    ("loading", "lazy".to_string()),
+  ]);
+  attributes.push(match placeholder_attribute {
+    "style" => ("style", add_background_placeholder(None, &meta.placeholder)),
+    attribute => (attribute, meta.placeholder.clone()),
+  });
+
+  let attributes = attributes
+    .iter()
+    .map(|(name, value)| format!("{}=\"{}\"", name, escape_attribute(value)))
+    .collect::<Vec<_>>();
+  format!("<img {}>", attributes.join(" "))
+}
+
+fn escape_attribute(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('"', "&quot;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
+
+/// Fills in the placeholder and the missing dimensions of an `<img>` element written in HTML,
+/// and lets it load lazily unless `loading` is set.
+fn enrich_markdown_html_image(
+  element: &mut Element,
+  placeholder_attribute: &str,
+  store: &Store,
+  options: &PreviewOptions,
+  file_path: &str,
+  aliases: Option<&HashMap<String, String>>,
+) -> Result<(), AttributeNameError> {
+  let Some(meta) = element
+    .get_attribute("src")
+    .and_then(|src| get_image_url("src", &src, file_path, aliases))
+    .and_then(|url| store.get_meta_from_url_and_options(url, options).ok())
+  else {
+    return Ok(());
+  };
+
+  for (name, value) in [
+    ("width", meta.width.to_string()),
+    ("height", meta.height.to_string()),
+    ("loading", "lazy".to_string()),
+  ] {
+    if !element.has_attribute(name) {
+      element.set_attribute(name, &value)?;
+    }
+  }
+
+  match placeholder_attribute {
+    "style" => {
+      let style = add_background_placeholder(element.get_attribute("style"), &meta.placeholder);
+      element.set_attribute("style", &style)?;
+    }
+    attribute if !element.has_attribute(attribute) => {
+      element.set_attribute(attribute, &meta.placeholder)?;
+    }
+    _ => {}
+  }
+  Ok(())
+}
+
+/// Lists the placeholders and dimensions of the images under the `nocojs` key of the front matter,
+/// in YAML or TOML depending on its delimiters. Documents without front matter get a YAML one.
+fn insert_front_matter(
+  patcher: &mut SourcePatcher,
+  code: &str,
+  front_matter: Option<(Span, MetadataBlockKind)>,
+  entries: &[(&str, PreviewMeta)],
+  file_path: &str,
+) {
+  let kind = front_matter.as_ref().map(|(_, kind)| *kind);
+  let mut map = String::new();
+  if kind == Some(MetadataBlockKind::PlusesStyle) {
+    for (specifier, meta) in entries {
+      map.push_str(&format!(
+        "[{}.{}]\nplaceholder = {}\nwidth = {}\nheight = {}\n",
+        FRONT_MATTER_KEY,
+        quote(specifier),
+        quote(&meta.placeholder),
+        meta.width,
+        meta.height
+      ));
+    }
+  } else {
+    map.push_str(&format!("{}:\n", FRONT_MATTER_KEY));
+    for (specifier, meta) in entries {
+      map.push_str(&format!(
+        "  {}:\n    placeholder: {}\n    width: {}\n    height: {}\n",
+        quote(specifier),
+        quote(&meta.placeholder),
+        meta.width,
+        meta.height
+      ));
+    }
+  }
+
+  let Some((span, _)) = front_matter else {
+    patcher.insert(0, format!("---\n{}---\n\n", map));
+    return;
+  };
+
+  let content = span.source_text(code);
+  let has_key = content.lines().any(|line| {
+    line.starts_with(&format!("{}:", FRONT_MATTER_KEY))
+      || line.starts_with(&format!("[{}", FRONT_MATTER_KEY))
+  });
+  if has_key {
+    create_log(
+      log::style_error(format!(
+        "The front matter of {} already has a \"{}\" key, the placeholders won't be added",
+        file_path, FRONT_MATTER_KEY
+      )),
+      LogLevel::Error,
+    );
+    return;
+  }
+
+  // Before the closing delimiter, the last line of the span, which may be followed by spaces
+  let closing_line = content.trim_end().rfind('\n').map_or(0, |index| index + 1);
+  patcher.insert(span.start + closing_line as u32, map);
+}
+
+/// Quotes a string for YAML and TOML, which share the escapes of double quoted strings.
+fn quote(value: &str) -> String {
+  format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
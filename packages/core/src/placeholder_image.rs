@@ -10,7 +10,8 @@ use reqwest::Client;
 use std::{collections::HashMap, io::Cursor, time::Instant};
 
 use crate::{
-  log::{create_log, style_info, LogLevel},
+  log::{create_log, style_error, style_info, LogLevel},
+  similarity::find_dimensions_for_similarity,
   transform::PreviewOptions,
 };
 
@@ -50,12 +51,327 @@ impl PlaceholderImageOutputKind {
   }
 }
 
+/// Region of the source image that was kept after trimming uniform borders.
+/// When a crop, rotation or flip is requested, the box is relative to the image after those.
+#[napi(object)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrimBox {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl TrimBox {
+  pub fn get_string_name(&self) -> String {
+    format!("{},{},{},{}", self.x, self.y, self.width, self.height)
+  }
+
+  pub fn from_string(s: &str) -> Option<TrimBox> {
+    let parts: Vec<u32> = s
+      .split(',')
+      .map(|part| part.trim().parse::<u32>())
+      .collect::<Result<_, _>>()
+      .ok()?;
+
+    match parts[..] {
+      [x, y, width, height] => Some(TrimBox {
+        x,
+        y,
+        width,
+        height,
+      }),
+      _ => None,
+    }
+  }
+}
+
+pub const DEFAULT_TRIM_TOLERANCE: u32 = 10;
+
+#[napi]
+#[derive(Debug, Clone, PartialEq)]
+pub enum CropUnit {
+  Pixels,
+  Percent,
+}
+
+impl CropUnit {
+  pub fn get_string_name(&self) -> String {
+    match self {
+      CropUnit::Pixels => "px".to_string(),
+      CropUnit::Percent => "%".to_string(),
+    }
+  }
+
+  pub fn from_string(s: &str) -> CropUnit {
+    match s {
+      "%" | "percent" => CropUnit::Percent,
+      _ => CropUnit::Pixels,
+    }
+  }
+}
+
+/// Crop rectangle applied to the source image before resizing.
+/// Values are source pixels, or percentages of the source dimensions when `unit` is `Percent`.
+#[napi(object)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CropRect {
+  pub x: f64,
+  pub y: f64,
+  pub width: f64,
+  pub height: f64,
+  pub unit: Option<CropUnit>,
+}
+
+impl CropRect {
+  pub fn get_string_name(&self) -> String {
+    format!(
+      "{},{},{},{}{}",
+      self.x,
+      self.y,
+      self.width,
+      self.height,
+      self
+        .unit
+        .clone()
+        .unwrap_or(CropUnit::Pixels)
+        .get_string_name()
+    )
+  }
+
+  /// Resolves the rectangle to pixel bounds `(x, y, width, height)`, clamped to the image.
+  /// Returns `None` when the clamped rectangle is empty.
+  pub fn resolve(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+    let (x, y, crop_width, crop_height) = match self.unit {
+      Some(CropUnit::Percent) => (
+        self.x / 100.0 * width as f64,
+        self.y / 100.0 * height as f64,
+        self.width / 100.0 * width as f64,
+        self.height / 100.0 * height as f64,
+      ),
+      _ => (self.x, self.y, self.width, self.height),
+    };
+
+    let x = (x.round().max(0.0) as u32).min(width);
+    let y = (y.round().max(0.0) as u32).min(height);
+    let crop_width = (crop_width.round().max(0.0) as u32).min(width - x);
+    let crop_height = (crop_height.round().max(0.0) as u32).min(height - y);
+
+    if crop_width == 0 || crop_height == 0 {
+      return None;
+    }
+
+    Some((x, y, crop_width, crop_height))
+  }
+}
+
+#[napi]
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlipDirection {
+  Horizontal,
+  Vertical,
+  Both,
+}
+
+impl FlipDirection {
+  pub fn get_string_name(&self) -> String {
+    match self {
+      FlipDirection::Horizontal => "horizontal".to_string(),
+      FlipDirection::Vertical => "vertical".to_string(),
+      FlipDirection::Both => "both".to_string(),
+    }
+  }
+
+  pub fn from_string(s: &str) -> Option<FlipDirection> {
+    match s {
+      "horizontal" => Some(FlipDirection::Horizontal),
+      "vertical" => Some(FlipDirection::Vertical),
+      "both" => Some(FlipDirection::Both),
+      _ => None,
+    }
+  }
+}
+
+/// Dimensions of the image after the crop and rotation in `options` are applied.
+pub fn transformed_dimensions(width: u32, height: u32, options: &PreviewOptions) -> (u32, u32) {
+  let (width, height) = match options.crop.as_ref().and_then(|c| c.resolve(width, height)) {
+    Some((_, _, crop_width, crop_height)) => (crop_width, crop_height),
+    None => (width, height),
+  };
+
+  match options.rotate {
+    Some(90) | Some(270) => (height, width),
+    _ => (width, height),
+  }
+}
+
+/// Dimensions of the region the placeholder was generated from.
+/// Used to keep the aspect ratio when the placeholder is wrapped with an SVG.
+pub fn source_dimensions(
+  original_width: u32,
+  original_height: u32,
+  trim_box: &Option<TrimBox>,
+  options: &PreviewOptions,
+) -> (u32, u32) {
+  match trim_box {
+    Some(trim_box) => (trim_box.width, trim_box.height),
+    None => transformed_dimensions(original_width, original_height, options),
+  }
+}
+
+#[napi]
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaceholderFormat {
+  Png,
+  Jpeg,
+  Webp,
+}
+
+impl PlaceholderFormat {
+  pub fn get_string_name(&self) -> String {
+    match self {
+      PlaceholderFormat::Png => "png".to_string(),
+      PlaceholderFormat::Jpeg => "jpeg".to_string(),
+      PlaceholderFormat::Webp => "webp".to_string(),
+    }
+  }
+
+  pub fn from_string(s: &str) -> PlaceholderFormat {
+    match s {
+      "jpeg" => PlaceholderFormat::Jpeg,
+      "webp" => PlaceholderFormat::Webp,
+      _ => PlaceholderFormat::Png,
+    }
+  }
+
+  pub fn get_mime_type(&self) -> &'static str {
+    match self {
+      PlaceholderFormat::Png => "image/png",
+      PlaceholderFormat::Jpeg => "image/jpeg",
+      PlaceholderFormat::Webp => "image/webp",
+    }
+  }
+}
+
+/// Encoder settings tried while searching for a placeholder that fits a byte budget.
+struct Encoding {
+  format: PlaceholderFormat,
+  quality: Option<u32>,
+  color_bits: u32,
+}
+
+const DEFAULT_ENCODING: Encoding = Encoding {
+  format: PlaceholderFormat::Png,
+  quality: None,
+  color_bits: 8,
+};
+
+/// Encodings ordered from the best looking to the smallest.
+const BUDGET_ENCODINGS: &[Encoding] = &[
+  DEFAULT_ENCODING,
+  Encoding {
+    format: PlaceholderFormat::Webp,
+    quality: None,
+    color_bits: 8,
+  },
+  Encoding {
+    format: PlaceholderFormat::Png,
+    quality: None,
+    color_bits: 6,
+  },
+  Encoding {
+    format: PlaceholderFormat::Jpeg,
+    quality: Some(85),
+    color_bits: 8,
+  },
+  Encoding {
+    format: PlaceholderFormat::Png,
+    quality: None,
+    color_bits: 5,
+  },
+  Encoding {
+    format: PlaceholderFormat::Jpeg,
+    quality: Some(65),
+    color_bits: 8,
+  },
+  Encoding {
+    format: PlaceholderFormat::Png,
+    quality: None,
+    color_bits: 4,
+  },
+  Encoding {
+    format: PlaceholderFormat::Jpeg,
+    quality: Some(45),
+    color_bits: 8,
+  },
+  Encoding {
+    format: PlaceholderFormat::Png,
+    quality: None,
+    color_bits: 3,
+  },
+];
+
+/// Parameters chosen when a placeholder is tuned to fit a byte budget (`max_bytes`).
+#[napi(object)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaceholderTuning {
+  pub width: u32,
+  pub height: u32,
+  pub format: PlaceholderFormat,
+  /// Encoder quality, only set for lossy formats.
+  pub quality: Option<u32>,
+  /// Bits kept per colour channel before encoding.
+  pub color_bits: u32,
+  /// Size of the final placeholder string, including the SVG wrapper when enabled.
+  pub bytes: u32,
+}
+
+impl PlaceholderTuning {
+  pub fn get_string_name(&self) -> String {
+    format!(
+      "{},{},{},{},{},{}",
+      self.format.get_string_name(),
+      self.quality.unwrap_or(0),
+      self.color_bits,
+      self.width,
+      self.height,
+      self.bytes
+    )
+  }
+
+  pub fn from_string(s: &str) -> Option<PlaceholderTuning> {
+    let parts: Vec<&str> = s.split(',').collect();
+    let [format, quality, color_bits, width, height, bytes] = parts[..] else {
+      return None;
+    };
+    let quality = quality.parse::<u32>().ok()?;
+
+    Some(PlaceholderTuning {
+      width: width.parse().ok()?,
+      height: height.parse().ok()?,
+      format: PlaceholderFormat::from_string(format),
+      quality: if quality == 0 { None } else { Some(quality) },
+      color_bits: color_bits.parse().ok()?,
+      bytes: bytes.parse().ok()?,
+    })
+  }
+}
+
 pub struct ProcessImageOutput {
   pub base64_str: String,
   pub width: u32,
   pub height: u32,
   pub original_width: u32,
   pub original_height: u32,
+  pub trim_box: Option<TrimBox>,
+  pub tuning: Option<PlaceholderTuning>,
+  /// SSIM of the placeholder against the original, when a target similarity was given.
+  pub similarity: Option<f64>,
+  /// Dominant colour of the (cropped / rotated) source image, eg: `#a0b1c2`.
+  /// Only computed when the metadata of the image is used.
+  pub dominant_color: Option<String>,
+  /// Format of the source image, eg: `jpeg` or `webp`.
+  pub format: String,
 }
 
 enum DynamicImageWrapper {
@@ -84,6 +400,7 @@ pub async fn download_and_process_image(
   client: &Client,
   url: &str,
   options: &PreviewOptions,
+  require_meta: bool,
 ) -> Result<ProcessImageOutput, Box<dyn std::error::Error>> {
   let download_time = Instant::now();
   create_log(
@@ -99,16 +416,21 @@ pub async fn download_and_process_image(
     LogLevel::Info,
   );
 
-  process_image(&bytes, url, options).await
+  process_image(&bytes, url, options, require_meta).await
 }
 
 /// Processes the image bytes and returns a base64 encoded string of the processed image.
-/// The processing includes resizing, converting to the specified output kind,
-/// and encoding as PNG.
+/// The processing includes cropping, rotating and flipping (if requested),
+/// trimming uniform borders (if enabled), resizing,
+/// converting to the specified output kind, and encoding as PNG.
+/// When `target_similarity` is set, the smallest size reaching that SSIM is picked.
+/// When `max_bytes` is set, the size, format and quantisation are tuned to fit the budget.
+/// The dominant colour is only computed with `require_meta`, eg: for `previewMeta()`.
 pub async fn process_image(
   bytes: &Bytes,
   url: &str,
   options: &PreviewOptions,
+  require_meta: bool,
 ) -> Result<ProcessImageOutput, Box<dyn std::error::Error>> {
   let process_time = Instant::now();
   let img = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
@@ -140,6 +462,26 @@ pub async fn process_image(
     }
   };
 
+  let (original_width, original_height) = (img.width(), img.height());
+
+  let img = apply_geometry(img, url, options);
+  let dominant_color = if require_meta {
+    Some(get_dominant_color_hex(&img)?)
+  } else {
+    None
+  };
+
+  let trim_box = if options.trim {
+    find_trim_box(&img, options.trim_tolerance)
+  } else {
+    None
+  };
+
+  let img = match &trim_box {
+    Some(trim_box) => img.crop_imm(trim_box.x, trim_box.y, trim_box.width, trim_box.height),
+    None => img,
+  };
+
   let img_rgb = {
     match options.output_kind {
       PlaceholderImageOutputKind::Normal => DynamicImageWrapper::Rgb(img.to_rgb8()),
@@ -170,24 +512,7 @@ pub async fn process_image(
   };
 
   let (width, height) = img_rgb.dimensions();
-  let aspect_ratio = height as f32 / width as f32;
-  let (new_width, new_height) = {
-    if options.width.is_some() && options.height.is_some() {
-      (options.width.unwrap(), options.height.unwrap())
-    } else if options.width.is_some() {
-      let new_width = options.width.unwrap();
-      let new_height = (new_width as f32 * aspect_ratio) as u32;
-      (new_width, new_height)
-    } else if options.height.is_some() {
-      let new_height = options.height.unwrap();
-      let new_width = (new_height as f32 / aspect_ratio) as u32;
-      (new_width, new_height)
-    } else {
-      let new_width = 16; // Default width
-      let new_height = (new_width as f32 * aspect_ratio) as u32;
-      (new_width, new_height)
-    }
-  };
+  let (new_width, new_height) = get_target_dimensions(width, height, options);
 
   let src_image = fir::images::Image::from_vec_u8(
     std::num::NonZeroU32::new(width).unwrap().into(),
@@ -196,15 +521,109 @@ pub async fn process_image(
     pixel_type,
   )?;
 
+  // Similarity targeting only makes sense when the size isn't given and the placeholder
+  // actually shows the image.
+  let similarity_target = match options.output_kind {
+    PlaceholderImageOutputKind::Normal
+    | PlaceholderImageOutputKind::Blurred
+    | PlaceholderImageOutputKind::Grayscale
+      if options.width.is_none() && options.height.is_none() =>
+    {
+      options.target_similarity
+    }
+    _ => None,
+  };
+
+  let (new_width, new_height, similarity) = match similarity_target {
+    Some(target) => {
+      let ((new_width, new_height), score) =
+        find_dimensions_for_similarity(&src_image, target, options)?;
+      if score < target {
+        create_log(
+          style_info(format!(
+            "Placeholder for {url} reached a similarity of {score:.3}, below the target of {target}"
+          )),
+          LogLevel::Verbose,
+        );
+      }
+      (new_width, new_height, Some(score))
+    }
+    None => (new_width, new_height, None),
+  };
+
+  let (base64_str, tuning) = match options.max_bytes {
+    Some(max_bytes) => {
+      let (base64_str, tuning) = encode_within_budget(
+        &src_image,
+        (new_width, new_height),
+        (width, height),
+        max_bytes,
+        color_type,
+        options,
+        url,
+      )?;
+      (base64_str, Some(tuning))
+    }
+    None => (
+      encode_placeholder(
+        &src_image,
+        (new_width, new_height),
+        (width, height),
+        &DEFAULT_ENCODING,
+        color_type,
+        options,
+      )?,
+      None,
+    ),
+  };
+
+  let (new_width, new_height) = tuning
+    .as_ref()
+    .map_or((new_width, new_height), |t| (t.width, t.height));
+
+  let elapsed = process_time.elapsed();
+  create_log(
+    style_info(format!("Processed image {url} in {elapsed:?}")),
+    LogLevel::Info,
+  );
+
+  Ok(ProcessImageOutput {
+    base64_str,
+    width: new_width,
+    height: new_height,
+    original_width,
+    original_height,
+    trim_box,
+    tuning,
+    similarity,
+    dominant_color,
+    format: format!("{:?}", img_format).to_lowercase(),
+  })
+}
+
+/// Resizes the source image to `dimensions`, encodes it with `encoding`
+/// and builds the placeholder string for the requested output kind.
+/// `source_dimensions` are the dimensions of the (cropped / trimmed) source image.
+fn encode_placeholder(
+  src_image: &Image,
+  dimensions: (u32, u32),
+  source_dimensions: (u32, u32),
+  encoding: &Encoding,
+  color_type: image::ExtendedColorType,
+  options: &PreviewOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+  let (new_width, new_height) = dimensions;
+  let (width, height) = source_dimensions;
+
   let mut dst_image = fir::images::Image::new(
     std::num::NonZeroU32::new(new_width).unwrap().into(),
     std::num::NonZeroU32::new(new_height).unwrap().into(),
-    pixel_type,
+    src_image.pixel_type(),
   );
 
   let mut resizer = fir::Resizer::new();
   resizer.resize(
-    &src_image,
+    src_image,
     &mut dst_image,
     &fir::ResizeOptions {
       algorithm: fir::ResizeAlg::Nearest,
@@ -212,28 +631,49 @@ pub async fn process_image(
     },
   )?;
 
-  // Step 5: Encode resized image as PNG
-  let mut png_bytes = Vec::new();
-  image::codecs::png::PngEncoder::new(&mut png_bytes).write_image(
-    dst_image.buffer(),
-    dst_image.width(),
-    dst_image.height(),
-    color_type,
-  )?;
+  if encoding.color_bits < 8 {
+    quantize(dst_image.buffer_mut(), encoding.color_bits);
+  }
+
+  let mut image_bytes = Vec::new();
+  match encoding.format {
+    PlaceholderFormat::Png => image::codecs::png::PngEncoder::new(&mut image_bytes).write_image(
+      dst_image.buffer(),
+      dst_image.width(),
+      dst_image.height(),
+      color_type,
+    )?,
+    PlaceholderFormat::Jpeg => image::codecs::jpeg::JpegEncoder::new_with_quality(
+      &mut image_bytes,
+      encoding.quality.unwrap_or(80) as u8,
+    )
+    .write_image(
+      dst_image.buffer(),
+      dst_image.width(),
+      dst_image.height(),
+      color_type,
+    )?,
+    PlaceholderFormat::Webp => image::codecs::webp::WebPEncoder::new_lossless(&mut image_bytes)
+      .write_image(
+        dst_image.buffer(),
+        dst_image.width(),
+        dst_image.height(),
+        color_type,
+      )?,
+  }
 
   let base64_str = {
     match options.output_kind {
       PlaceholderImageOutputKind::Normal | PlaceholderImageOutputKind::Grayscale => {
-        let png_data = format!(
-          "data:image/png;base64,{}",
-          general_purpose::STANDARD.encode(&png_bytes)
-        );
-
-        png_data
+        format!(
+          "data:{};base64,{}",
+          encoding.format.get_mime_type(),
+          general_purpose::STANDARD.encode(&image_bytes)
+        )
       }
       PlaceholderImageOutputKind::Blurred => {
-        let data_src = general_purpose::STANDARD.encode(&png_bytes);
-        create_blurred_preview_url(&data_src, width, height)
+        let data_src = general_purpose::STANDARD.encode(&image_bytes);
+        create_blurred_preview_url(&data_src, encoding.format.get_mime_type(), width, height)
       }
       PlaceholderImageOutputKind::AverageColor | PlaceholderImageOutputKind::DominantColor => {
         let color_type = if options.output_kind == PlaceholderImageOutputKind::AverageColor {
@@ -251,21 +691,231 @@ pub async fn process_image(
     }
   };
 
-  let elapsed = process_time.elapsed();
+  Ok(base64_str)
+}
+
+/// Searches for the best looking placeholder whose final size fits in `max_bytes`.
+/// Candidates are tried from the largest dimensions down, and for each size the encodings are
+/// tried from the best looking to the smallest. The first candidate that fits wins.
+/// If nothing fits, the smallest candidate is returned and an error is logged.
+fn encode_within_budget(
+  src_image: &Image,
+  dimensions: (u32, u32),
+  source_dimensions: (u32, u32),
+  max_bytes: u32,
+  color_type: image::ExtendedColorType,
+  options: &PreviewOptions,
+  url: &str,
+) -> Result<(String, PlaceholderTuning), Box<dyn std::error::Error>> {
+  // Solid colour placeholders don't depend on the encoding, only the size matters.
+  let encodings: &[Encoding] = match options.output_kind {
+    PlaceholderImageOutputKind::Normal
+    | PlaceholderImageOutputKind::Blurred
+    | PlaceholderImageOutputKind::Grayscale => BUDGET_ENCODINGS,
+    PlaceholderImageOutputKind::DominantColor
+    | PlaceholderImageOutputKind::AverageColor
+    | PlaceholderImageOutputKind::Transparent => &[DEFAULT_ENCODING],
+  };
+
+  let mut smallest: Option<(String, PlaceholderTuning)> = None;
+  let mut scale = 1.0_f32;
+
+  loop {
+    let candidate_width = ((dimensions.0 as f32 * scale).round() as u32).max(1);
+    let candidate_height = ((dimensions.1 as f32 * scale).round() as u32).max(1);
+
+    for encoding in encodings {
+      let base64_str = encode_placeholder(
+        src_image,
+        (candidate_width, candidate_height),
+        source_dimensions,
+        encoding,
+        color_type,
+        options,
+      )?;
+
+      let bytes =
+        if options.wrap_with_svg && options.output_kind != PlaceholderImageOutputKind::Blurred {
+          wrap_with_svg(base64_str.clone(), source_dimensions.0, source_dimensions.1).len()
+        } else {
+          base64_str.len()
+        } as u32;
+
+      let tuning = PlaceholderTuning {
+        width: candidate_width,
+        height: candidate_height,
+        format: encoding.format.clone(),
+        quality: encoding.quality,
+        color_bits: encoding.color_bits,
+        bytes,
+      };
+
+      if bytes <= max_bytes {
+        return Ok((base64_str, tuning));
+      }
+
+      if smallest.as_ref().is_none_or(|(_, s)| bytes < s.bytes) {
+        smallest = Some((base64_str, tuning));
+      }
+    }
+
+    if candidate_width == 1 && candidate_height == 1 {
+      break;
+    }
+    scale *= 0.85;
+  }
+
+  let (base64_str, tuning) = smallest.ok_or("No placeholder candidates were generated")?;
   create_log(
-    style_info(format!("Processed image {url} in {elapsed:?}")),
-    LogLevel::Info,
+    style_error(format!(
+      "Could not fit the placeholder for {url} in {max_bytes} bytes. Using the smallest candidate ({} bytes)",
+      tuning.bytes
+    )),
+    LogLevel::Error,
   );
 
-  Ok(ProcessImageOutput {
-    base64_str,
-    width: new_width,
-    height: new_height,
-    original_width: width,
-    original_height: height,
+  Ok((base64_str, tuning))
+}
+
+/// Keeps only the top `bits` of every channel, which makes the encoded image compress better.
+fn quantize(buffer: &mut [u8], bits: u32) {
+  let shift = 8 - bits.clamp(1, 8);
+  if shift == 0 {
+    return;
+  }
+  let half_step = 1u8 << (shift - 1);
+  for value in buffer.iter_mut() {
+    *value = ((*value >> shift) << shift) | half_step;
+  }
+}
+
+/// Applies the crop, rotation and flip from the options, in that order.
+/// Crop coordinates always refer to the original, unrotated image.
+fn apply_geometry(img: DynamicImage, url: &str, options: &PreviewOptions) -> DynamicImage {
+  let img = match &options.crop {
+    Some(crop) => match crop.resolve(img.width(), img.height()) {
+      Some((x, y, width, height)) => img.crop_imm(x, y, width, height),
+      None => {
+        create_log(
+          style_info(format!(
+            "Ignoring crop for {url}: the crop rectangle is outside the image"
+          )),
+          LogLevel::Error,
+        );
+        img
+      }
+    },
+    None => img,
+  };
+
+  let img = match options.rotate {
+    None | Some(0) => img,
+    Some(90) => img.rotate90(),
+    Some(180) => img.rotate180(),
+    Some(270) => img.rotate270(),
+    Some(other) => {
+      create_log(
+        style_info(format!(
+          "Ignoring rotate for {url}: {other} is not one of 90, 180 or 270"
+        )),
+        LogLevel::Error,
+      );
+      img
+    }
+  };
+
+  match options.flip {
+    Some(FlipDirection::Horizontal) => img.fliph(),
+    Some(FlipDirection::Vertical) => img.flipv(),
+    Some(FlipDirection::Both) => img.fliph().flipv(),
+    None => img,
+  }
+}
+
+/// Finds the smallest box that contains everything except a uniform border.
+/// The border colour is taken from the top-left pixel, and a pixel is treated as part of the
+/// border when every channel is within `tolerance` of it.
+/// Returns `None` when there is nothing to trim, or when the whole image is uniform.
+fn find_trim_box(img: &DynamicImage, tolerance: u32) -> Option<TrimBox> {
+  let rgba = img.to_rgba8();
+  let (width, height) = rgba.dimensions();
+  if width == 0 || height == 0 {
+    return None;
+  }
+
+  let reference = *rgba.get_pixel(0, 0);
+  let is_border = |x: u32, y: u32| {
+    let pixel = rgba.get_pixel(x, y);
+    pixel
+      .0
+      .iter()
+      .zip(reference.0.iter())
+      .all(|(a, b)| (*a as i32 - *b as i32).unsigned_abs() <= tolerance)
+  };
+  let row_is_border = |y: u32| (0..width).all(|x| is_border(x, y));
+  let column_is_border = |x: u32, top: u32, bottom: u32| (top..bottom).all(|y| is_border(x, y));
+
+  let top = (0..height).find(|&y| !row_is_border(y))?;
+  let bottom = (top..height).rev().find(|&y| !row_is_border(y))? + 1;
+  let left = (0..width).find(|&x| !column_is_border(x, top, bottom))?;
+  let right = (left..width)
+    .rev()
+    .find(|&x| !column_is_border(x, top, bottom))?
+    + 1;
+
+  if left == 0 && top == 0 && right == width && bottom == height {
+    return None;
+  }
+
+  Some(TrimBox {
+    x: left,
+    y: top,
+    width: right - left,
+    height: bottom - top,
   })
 }
 
+/// Calculates the placeholder dimensions.
+/// Explicit `width`/`height` take precedence. Otherwise, when `target_area` is set, the dimensions
+/// are chosen so the placeholder has roughly that many pixels whatever its aspect ratio.
+/// The fallback is a 16px wide placeholder.
+///
+/// Both sides are then scaled by the same factor so the shorter side is at least `min_side` and
+/// the longer side at most `max_side`. `max_side` wins when both can't hold, and inverted bounds are swapped.
+pub fn get_target_dimensions(width: u32, height: u32, options: &PreviewOptions) -> (u32, u32) {
+  let aspect_ratio = height as f32 / width as f32;
+  let (new_width, new_height) = match (options.width, options.height, options.target_area) {
+    (Some(new_width), Some(new_height), _) => (new_width as f32, new_height as f32),
+    (Some(new_width), None, _) => (new_width as f32, (new_width as f32 * aspect_ratio).floor()),
+    (None, Some(new_height), _) => (
+      (new_height as f32 / aspect_ratio).floor(),
+      new_height as f32,
+    ),
+    (None, None, Some(target_area)) => {
+      let new_width = (target_area as f32 / aspect_ratio).sqrt();
+      (new_width.round(), (new_width * aspect_ratio).round())
+    }
+    (None, None, None) => {
+      let new_width = 16.0; // Default width
+      (new_width, (new_width * aspect_ratio).floor())
+    }
+  };
+
+  let (min_side, max_side) = match (options.min_side, options.max_side) {
+    (Some(min), Some(max)) if min > max => (Some(max), Some(min)),
+    bounds => bounds,
+  };
+  let shorter = new_width.min(new_height).max(1.0);
+  let longer = new_width.max(new_height).max(1.0);
+  let mut scale = min_side.map_or(1.0, |min| (min as f32 / shorter).max(1.0));
+  if let Some(max) = max_side {
+    scale = scale.min(max as f32 / longer);
+  }
+
+  let scale_side = |side: f32| ((side * scale).round() as u32).max(1);
+  (scale_side(new_width), scale_side(new_height))
+}
+
 #[derive(PartialEq)]
 enum ColorType {
   Dominant,
@@ -316,6 +966,15 @@ fn get_color_from_image(
   Ok((dominant.0, dominant.1, dominant.2))
 }
 
+/// Dominant colour of the image as a hex string, computed on a small thumbnail.
+fn get_dominant_color_hex(img: &DynamicImage) -> Result<String, Box<dyn std::error::Error>> {
+  let thumbnail = img.thumbnail(32, 32).to_rgb8();
+  let (width, height) = thumbnail.dimensions();
+  let image = Image::from_vec_u8(width, height, thumbnail.into_raw(), fir::PixelType::U8x3)?;
+  let (r, g, b) = get_color_from_image(&image, ColorType::Dominant)?;
+  Ok(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
 fn create_base64_rectangle(
   width: u32,
   height: u32,
@@ -335,12 +994,13 @@ fn create_base64_rectangle(
   Ok(format!("data:image/png;base64,{}", base64_string))
 }
 
-fn create_blurred_preview_url(data_src: &str, width: u32, height: u32) -> String {
+fn create_blurred_preview_url(data_src: &str, mime_type: &str, width: u32, height: u32) -> String {
   let svg = format!(
-    r#"<svg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 {w} {h}' width='{w}' height='{h}'><filter id='b' color-interpolation-filters='sRGB'><feGaussianBlur stdDeviation='{d}'/><feColorMatrix values='1 0 0 0 0 0 1 0 0 0 0 0 1 0 0 0 0 0 100 -1' result='s'/><feFlood x='0' y='0' width='100%' height='100%'/><feComposite operator='out' in='s'/><feComposite in2='SourceGraphic'/><feGaussianBlur stdDeviation='{d}'/></filter><image width='100%' height='100%' x='0' y='0' preserveAspectRatio='none' style='filter: url(#b);' href='data:image/png;base64,___DATA___'/></svg>"#,
+    r#"<svg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 {w} {h}' width='{w}' height='{h}'><filter id='b' color-interpolation-filters='sRGB'><feGaussianBlur stdDeviation='{d}'/><feColorMatrix values='1 0 0 0 0 0 1 0 0 0 0 0 1 0 0 0 0 0 100 -1' result='s'/><feFlood x='0' y='0' width='100%' height='100%'/><feComposite operator='out' in='s'/><feComposite in2='SourceGraphic'/><feGaussianBlur stdDeviation='{d}'/></filter><image width='100%' height='100%' x='0' y='0' preserveAspectRatio='none' style='filter: url(#b);' href='data:{m};base64,___DATA___'/></svg>"#,
     w = width,
     h = height,
-    d = (width as f32 * 0.05).round()
+    d = (width as f32 * 0.05).round(),
+    m = mime_type
   );
 
   let formatted = format!("data:image/svg+xml,{}", urlencoding::encode(&svg));
@@ -0,0 +1,107 @@
+use std::{
+  collections::HashMap,
+  path::{self, Path, PathBuf},
+};
+
+use url::Url;
+
+/// Resolves `specifier` relative to the module at `file_path`, the way `import` and
+/// `new URL(specifier, import.meta.url)` do, eg: `./hero.jpg` or `../assets/hero.jpg`.
+pub fn resolve_module_relative(specifier: &str, file_path: &str) -> Option<Url> {
+  let module_url = Url::from_file_path(path::absolute(file_path).ok()?).ok()?;
+  module_url.join(specifier).ok()
+}
+
+/// Resolves the image specifiers that depend on the importing file to the key of a local image
+/// (see [`get_local_image_key`]):
+/// - paths relative to the importing file, eg: `./hero.jpg` or `../assets/hero.jpg`
+/// - aliased paths, eg: `@/assets/hero.jpg` with the alias `{ "@/assets": "./src/assets" }`.
+///   Alias targets are directories, relative targets are resolved against the working directory.
+///
+/// Other specifiers (URLs, public paths and package paths) are returned unchanged,
+/// they are looked up by [`find_local_image`] when the image is read.
+pub fn resolve_image_specifier(
+  specifier: &str,
+  file_path: &str,
+  aliases: Option<&HashMap<String, String>>,
+) -> String {
+  if specifier.starts_with("./") || specifier.starts_with("../") {
+    return match resolve_module_relative(specifier, file_path) {
+      Some(url) => get_local_image_key(&url),
+      None => specifier.to_string(),
+    };
+  }
+
+  let alias = aliases.and_then(|aliases| {
+    aliases
+      .iter()
+      .filter_map(|(alias, target)| Some((alias, target, strip_alias(specifier, alias)?)))
+      // The most specific alias wins, eg: `@/assets` over `@`
+      .max_by_key(|(alias, _, _)| alias.len())
+  });
+
+  let Some((_, target, rest)) = alias else {
+    return specifier.to_string();
+  };
+
+  path::absolute(target)
+    .ok()
+    .and_then(|target| Url::from_directory_path(target).ok())
+    .and_then(|target| target.join(rest.trim_start_matches('/')).ok())
+    .map(|url| get_local_image_key(&url))
+    .unwrap_or_else(|| specifier.to_string())
+}
+
+/// Returns the key a local image is processed and cached under: its path relative to the working
+/// directory, eg: `./src/assets/hero.jpg`, so the cache doesn't depend on where the project is.
+/// Images outside the working directory keep their `file://` URL.
+pub fn get_local_image_key(url: &Url) -> String {
+  let relative_path = url.to_file_path().ok().and_then(|image_path| {
+    let relative_path = image_path
+      .strip_prefix(std::env::current_dir().ok()?)
+      .ok()?
+      .iter()
+      .map(|component| component.to_str())
+      .collect::<Option<Vec<_>>>()?;
+    Some(format!("./{}", relative_path.join("/")))
+  });
+  relative_path.unwrap_or_else(|| url.to_string())
+}
+
+/// Returns the rest of `specifier` when it starts with `alias` followed by a path separator.
+fn strip_alias<'s>(specifier: &'s str, alias: &str) -> Option<&'s str> {
+  let rest = specifier.strip_prefix(alias)?;
+  (alias.ends_with('/') || rest.starts_with('/')).then_some(rest)
+}
+
+/// Finds an image that isn't relative to the importing file. Looked up in order:
+/// - the working directory for the keys of local images, eg: `./src/assets/hero.jpg`
+/// - the public directories, eg: `/images/hero.jpg` in `public/images/hero.jpg`
+/// - for package paths, the `node_modules` directories above the importing file,
+///   eg: `@acme/brand/logo.png` in `node_modules/@acme/brand/logo.png`
+pub fn find_local_image(
+  specifier: &str,
+  public_dirs: &[String],
+  file_path: &str,
+) -> Option<PathBuf> {
+  if specifier.starts_with("./") {
+    return Some(PathBuf::from(specifier)).filter(|image_path| image_path.is_file());
+  }
+
+  let relative_path = specifier.strip_prefix("/").unwrap_or(specifier);
+
+  let in_public_dir = public_dirs
+    .iter()
+    .map(|public_dir| Path::new(public_dir).join(relative_path))
+    .find(|image_path| image_path.is_file());
+  if in_public_dir.is_some() || specifier.starts_with('/') {
+    return in_public_dir;
+  }
+
+  let file_path = path::absolute(file_path).ok()?;
+  file_path
+    .ancestors()
+    .skip(1)
+    .map(|dir| dir.join("node_modules").join(specifier))
+    .find(|image_path| image_path.is_file())
+}
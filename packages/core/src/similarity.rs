@@ -0,0 +1,165 @@
+use fast_image_resize::{self as fir, images::Image};
+
+use crate::{placeholder_image::get_target_dimensions, transform::PreviewOptions};
+
+/// Placeholder widths tried when searching for the smallest placeholder that reaches the target
+/// similarity. Widths larger than the reference image are skipped.
+const CANDIDATE_WIDTHS: &[u32] = &[4, 6, 8, 10, 12, 16, 20, 24, 32, 40, 48];
+
+/// Width of the reference downscale that candidates are compared against.
+const REFERENCE_WIDTH: u32 = 64;
+
+/// Side of the square window used to compute local SSIM values.
+const SSIM_WINDOW: u32 = 8;
+
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Finds the smallest placeholder dimensions whose upscaled rendition reaches `target` SSIM
+/// against a reference downscale of the source image.
+/// Returns the chosen dimensions and the achieved score. When no candidate reaches the target,
+/// the largest candidate is returned with its score.
+pub fn find_dimensions_for_similarity(
+  src_image: &Image,
+  target: f64,
+  options: &PreviewOptions,
+) -> Result<((u32, u32), f64), Box<dyn std::error::Error>> {
+  let reference_width = src_image.width().min(REFERENCE_WIDTH);
+  let reference_dimensions = get_target_dimensions(
+    src_image.width(),
+    src_image.height(),
+    &PreviewOptions {
+      width: Some(reference_width),
+      height: None,
+      min_side: None,
+      max_side: None,
+      ..options.clone()
+    },
+  );
+  let reference = resize(
+    src_image,
+    reference_dimensions,
+    fir::ResizeAlg::Convolution(fir::FilterType::Lanczos3),
+  )?;
+  let reference_luma = to_luma(&reference);
+
+  let mut best: Option<((u32, u32), f64)> = None;
+
+  for &candidate_width in CANDIDATE_WIDTHS
+    .iter()
+    .filter(|&&width| width <= reference_width)
+  {
+    let dimensions = get_target_dimensions(
+      src_image.width(),
+      src_image.height(),
+      &PreviewOptions {
+        width: Some(candidate_width),
+        height: None,
+        ..options.clone()
+      },
+    );
+
+    // Downscale the same way the placeholder is generated,
+    // then upscale the way a browser would stretch it over the real image.
+    let candidate = resize(src_image, dimensions, fir::ResizeAlg::Nearest)?;
+    let upscaled = resize(
+      &candidate,
+      reference_dimensions,
+      fir::ResizeAlg::Convolution(fir::FilterType::Bilinear),
+    )?;
+
+    let score = ssim(&reference_luma, &to_luma(&upscaled), reference_dimensions);
+    best = Some((dimensions, score));
+
+    if score >= target {
+      break;
+    }
+  }
+
+  // Images narrower than the smallest candidate are used at their full size.
+  Ok(best.unwrap_or((reference_dimensions, 1.0)))
+}
+
+fn resize(
+  src_image: &Image,
+  dimensions: (u32, u32),
+  algorithm: fir::ResizeAlg,
+) -> Result<Image<'static>, Box<dyn std::error::Error>> {
+  let mut dst_image = Image::new(dimensions.0, dimensions.1, src_image.pixel_type());
+  let mut resizer = fir::Resizer::new();
+  resizer.resize(
+    src_image,
+    &mut dst_image,
+    &fir::ResizeOptions {
+      algorithm,
+      ..fir::ResizeOptions::default()
+    },
+  )?;
+  Ok(dst_image)
+}
+
+/// Converts an RGB or grayscale image into a luma plane.
+fn to_luma(image: &Image) -> Vec<f64> {
+  match image.pixel_type() {
+    fir::PixelType::U8x3 => image
+      .buffer()
+      .chunks_exact(3)
+      .map(|rgb| 0.299 * rgb[0] as f64 + 0.587 * rgb[1] as f64 + 0.114 * rgb[2] as f64)
+      .collect(),
+    _ => image.buffer().iter().map(|v| *v as f64).collect(),
+  }
+}
+
+/// Mean structural similarity of two luma planes of the same dimensions.
+/// Local statistics are computed over square windows with a stride of half a window.
+fn ssim(a: &[f64], b: &[f64], dimensions: (u32, u32)) -> f64 {
+  let (width, height) = dimensions;
+  let window_width = SSIM_WINDOW.min(width);
+  let window_height = SSIM_WINDOW.min(height);
+  let stride_x = (window_width / 2).max(1);
+  let stride_y = (window_height / 2).max(1);
+
+  let mut total = 0.0;
+  let mut windows = 0;
+
+  let mut y = 0;
+  while y + window_height <= height {
+    let mut x = 0;
+    while x + window_width <= width {
+      total += window_ssim(a, b, width, (x, y), (window_width, window_height));
+      windows += 1;
+      x += stride_x;
+    }
+    y += stride_y;
+  }
+
+  if windows == 0 {
+    return 1.0;
+  }
+  total / windows as f64
+}
+
+fn window_ssim(a: &[f64], b: &[f64], width: u32, origin: (u32, u32), size: (u32, u32)) -> f64 {
+  let count = (size.0 * size.1) as f64;
+  let pixels = (origin.1..origin.1 + size.1)
+    .flat_map(|y| (origin.0..origin.0 + size.0).map(move |x| (y * width + x) as usize));
+
+  let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+  for index in pixels {
+    let (va, vb) = (a[index], b[index]);
+    sum_a += va;
+    sum_b += vb;
+    sum_aa += va * va;
+    sum_bb += vb * vb;
+    sum_ab += va * vb;
+  }
+
+  let mean_a = sum_a / count;
+  let mean_b = sum_b / count;
+  let variance_a = sum_aa / count - mean_a * mean_a;
+  let variance_b = sum_bb / count - mean_b * mean_b;
+  let covariance = sum_ab / count - mean_a * mean_b;
+
+  ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+    / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (variance_a + variance_b + SSIM_C2))
+}
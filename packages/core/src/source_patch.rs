@@ -0,0 +1,190 @@
+use oxc::span::Span;
+use oxc_sourcemap::{SourceMap, SourceMapBuilder};
+
+/// A replacement of the source text between `start` and `end` (byte offsets).
+/// Insertions are patches where `start == end`.
+#[derive(Debug, Clone)]
+struct SourcePatch {
+  start: u32,
+  end: u32,
+  replacement: String,
+}
+
+/// Collects text replacements and splices them into the original source,
+/// leaving everything else (comments, formatting, line numbers) untouched.
+#[derive(Debug, Default)]
+pub struct SourcePatcher {
+  patches: Vec<SourcePatch>,
+}
+
+/// Line and column in both the original and the generated text.
+/// Columns are counted in UTF-16 code units, as required by sourcemaps.
+#[derive(Debug, Default, Clone, Copy)]
+struct Position {
+  line: u32,
+  column: u32,
+}
+
+impl Position {
+  fn advance(&mut self, text: &str) {
+    for ch in text.chars() {
+      if ch == '\n' {
+        self.line += 1;
+        self.column = 0;
+      } else {
+        self.column += ch.len_utf16() as u32;
+      }
+    }
+  }
+}
+
+impl SourcePatcher {
+  pub fn new() -> Self {
+    SourcePatcher::default()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.patches.is_empty()
+  }
+
+  /// Replaces the text covered by `span` with `replacement`.
+  pub fn replace(&mut self, span: Span, replacement: String) {
+    self.patches.push(SourcePatch {
+      start: span.start,
+      end: span.end,
+      replacement,
+    });
+  }
+
+  /// Inserts `text` at the byte offset `at`.
+  pub fn insert(&mut self, at: u32, text: String) {
+    self.patches.push(SourcePatch {
+      start: at,
+      end: at,
+      replacement: text,
+    });
+  }
+
+  /// Removes the text covered by `span`.
+  pub fn remove(&mut self, span: Span) {
+    self.replace(span, String::new());
+  }
+
+  /// Moves the patches to another source, eg: from the virtual module of a component to the component.
+  /// `map` returns the span and the replacement in the other source, or None to drop the patch.
+  pub fn remap(&self, mut map: impl FnMut(Span, &str) -> Option<(Span, String)>) -> SourcePatcher {
+    let patches = self
+      .patches
+      .iter()
+      .filter_map(|patch| {
+        let (span, replacement) = map(Span::new(patch.start, patch.end), &patch.replacement)?;
+        Some(SourcePatch {
+          start: span.start,
+          end: span.end,
+          replacement,
+        })
+      })
+      .collect();
+    SourcePatcher { patches }
+  }
+
+  /// Applies the patches to `source` and returns the patched code with a sourcemap.
+  /// Unchanged text is mapped line by line to itself, and every replacement is mapped to the
+  /// start of the text it replaced, so the map is an identity outside the patched regions.
+  /// Patches that overlap an earlier patch are dropped.
+  pub fn apply(&self, source: &str, source_map_path: &str) -> (String, SourceMap) {
+    let mut patches = self.patches.clone();
+    // Stable sort keeps insertions at the same offset in the order they were added.
+    patches.sort_by_key(|patch| (patch.start, patch.end));
+
+    let mut builder = SourceMapBuilder::default();
+    let source_id = builder.set_source_and_content(source_map_path, source);
+
+    let mut code = String::with_capacity(source.len());
+    let mut original = Position::default();
+    let mut generated = Position::default();
+    let mut cursor = 0usize;
+
+    for patch in patches {
+      let (start, end) = (patch.start as usize, patch.end as usize);
+      if start < cursor || end > source.len() {
+        continue;
+      }
+
+      let unchanged = &source[cursor..start];
+      copy_unchanged(
+        unchanged,
+        &mut code,
+        &mut builder,
+        source_id,
+        &mut original,
+        &mut generated,
+      );
+
+      builder.add_token(
+        generated.line,
+        generated.column,
+        original.line,
+        original.column,
+        Some(source_id),
+        None,
+      );
+      code.push_str(&patch.replacement);
+      generated.advance(&patch.replacement);
+      original.advance(&source[start..end]);
+      cursor = end;
+    }
+
+    copy_unchanged(
+      &source[cursor..],
+      &mut code,
+      &mut builder,
+      source_id,
+      &mut original,
+      &mut generated,
+    );
+
+    (code, builder.into_sourcemap())
+  }
+}
+
+/// Copies text that wasn't patched, adding a mapping at its start and at the start of every line.
+fn copy_unchanged(
+  text: &str,
+  code: &mut String,
+  builder: &mut SourceMapBuilder,
+  source_id: u32,
+  original: &mut Position,
+  generated: &mut Position,
+) {
+  if text.is_empty() {
+    return;
+  }
+
+  builder.add_token(
+    generated.line,
+    generated.column,
+    original.line,
+    original.column,
+    Some(source_id),
+    None,
+  );
+
+  let mut lines = text.split_inclusive('\n').peekable();
+  while let Some(line) = lines.next() {
+    code.push_str(line);
+    original.advance(line);
+    generated.advance(line);
+
+    if line.ends_with('\n') && lines.peek().is_some() {
+      builder.add_token(
+        generated.line,
+        generated.column,
+        original.line,
+        original.column,
+        Some(source_id),
+        None,
+      );
+    }
+  }
+}
@@ -0,0 +1,117 @@
+use oxc_sourcemap::{SourceMap, Token};
+
+use crate::log::{self, create_log, LogLevel};
+
+/// Composes the sourcemap of a transform with `input_sourcemap`, the sourcemap (JSON) of its input.
+/// An input sourcemap that can't be parsed is logged, and `map` is returned as is.
+pub fn compose_input_sourcemap(
+  map: SourceMap,
+  input_sourcemap: Option<&str>,
+  file_path: &str,
+) -> SourceMap {
+  let Some(input_sourcemap) = input_sourcemap else {
+    return map;
+  };
+
+  match SourceMap::from_json_string(input_sourcemap) {
+    Ok(input_map) => compose_sourcemaps(&map, &input_map),
+    Err(e) => {
+      create_log(
+        log::style_error(format!(
+          "Failed to parse the input sourcemap of {}. The sourcemap won't be composed. Error: {}",
+          file_path, e
+        )),
+        LogLevel::Error,
+      );
+      map
+    }
+  }
+}
+
+/// Composes the sourcemap of a transform with the sourcemap of its input.
+/// `generated` maps the transformed code to the code nocojs received, and `input` maps that code
+/// to the real original sources (e.g. produced by TypeScript, Vue or MDX loaders).
+/// The returned map points from the transformed code directly to the original sources.
+///
+/// A token of `generated` maps the text up to the next token of its line. When that text was
+/// copied unchanged, eg: the lines between the patches of `SourcePatcher`, the segments of `input`
+/// inside it are kept at the same offsets. Otherwise it's mapped like the segment it starts in,
+/// or the first segment of its line. Segments that have no counterpart in `input` are left unmapped.
+pub fn compose_sourcemaps(generated: &SourceMap, input: &SourceMap) -> SourceMap {
+  let input_lines = input.generate_lookup_table();
+  let generated_tokens = generated.get_tokens().collect::<Vec<_>>();
+  let mut tokens = vec![];
+
+  for (index, token) in generated_tokens.iter().enumerate() {
+    let (src_line, src_col) = (token.get_src_line(), token.get_src_col());
+    let line_segments: &[Token] = input_lines
+      .get(src_line as usize)
+      .copied()
+      .unwrap_or_default();
+
+    // The end of the copied text in the input, None when the text was replaced
+    let next = generated_tokens
+      .get(index + 1)
+      .filter(|next| next.get_dst_line() == token.get_dst_line());
+    let copied_end = match next {
+      None => Some(u32::MAX),
+      Some(next)
+        if next.get_src_line() == src_line
+          && next.get_src_col().checked_sub(src_col)
+            == next.get_dst_col().checked_sub(token.get_dst_col()) =>
+      {
+        Some(next.get_src_col())
+      }
+      Some(_) => None,
+    };
+
+    let start = line_segments
+      .iter()
+      .rfind(|segment| segment.get_dst_col() <= src_col)
+      .or_else(|| {
+        copied_end
+          .is_none()
+          .then(|| line_segments.first())
+          .flatten()
+      })
+      .map(|segment| (token.get_dst_col(), segment));
+    let inner = copied_end.into_iter().flat_map(|end| {
+      line_segments
+        .iter()
+        .filter(move |segment| segment.get_dst_col() > src_col && segment.get_dst_col() < end)
+        .map(|segment| {
+          (
+            token.get_dst_col() + segment.get_dst_col() - src_col,
+            segment,
+          )
+        })
+    });
+
+    for (dst_col, original) in start.into_iter().chain(inner) {
+      if original.get_source_id().is_none() {
+        continue;
+      }
+      tokens.push(Token::new(
+        token.get_dst_line(),
+        dst_col,
+        original.get_src_line(),
+        original.get_src_col(),
+        original.get_source_id(),
+        original.get_name_id(),
+      ));
+    }
+  }
+
+  SourceMap::new(
+    generated.get_file().cloned(),
+    input.get_names().cloned().collect(),
+    input.get_source_root().map(|root| root.to_string()),
+    input.get_sources().cloned().collect(),
+    input
+      .get_source_contents()
+      .map(|content| content.cloned())
+      .collect(),
+    tokens.into_boxed_slice(),
+    None,
+  )
+}
@@ -4,7 +4,10 @@ use std::{
 };
 
 use crate::{
-  placeholder_image::{wrap_with_svg, PlaceholderImageOutputKind},
+  placeholder_image::{
+    source_dimensions, transformed_dimensions, wrap_with_svg, PlaceholderImageOutputKind,
+    PlaceholderTuning, ProcessImageOutput, TrimBox,
+  },
   transform::PreviewOptions,
 };
 
@@ -26,6 +29,55 @@ pub struct StoreDataItem {
   db_action: DbAction,
   original_width: u32,
   original_height: u32,
+  trim_box: Option<TrimBox>,
+  tuning: Option<PlaceholderTuning>,
+  similarity: Option<f64>,
+  dominant_color: Option<String>,
+  format: Option<String>,
+}
+
+impl StoreDataItem {
+  fn to_row(&self) -> ImageRow {
+    ImageRow {
+      id: self.id,
+      url: self.url.clone(),
+      placeholder: self.placeholder.clone(),
+      preview_type: PlaceholderImageOutputKind::get_string_name(&self.preview_type.clone()),
+      cache_key: self.cache_key.clone(),
+      original_width: self.original_width,
+      original_height: self.original_height,
+      trim_box: self.trim_box.as_ref().map(TrimBox::get_string_name),
+      tuning: self.tuning.as_ref().map(PlaceholderTuning::get_string_name),
+      similarity: self.similarity,
+      dominant_color: self.dominant_color.clone(),
+      format: self.format.clone(),
+    }
+  }
+}
+
+/// A row of the `images` table, as read from or written to the database.
+pub struct ImageRow {
+  pub id: i32,
+  pub url: String,
+  pub placeholder: String,
+  pub preview_type: String,
+  pub cache_key: String,
+  pub original_width: u32,
+  pub original_height: u32,
+  pub trim_box: Option<String>,
+  pub tuning: Option<String>,
+  pub similarity: Option<f64>,
+  pub dominant_color: Option<String>,
+  pub format: Option<String>,
+}
+
+/// Metadata of a processed image, used to replace `previewMeta()` calls.
+pub struct PreviewMeta {
+  pub placeholder: String,
+  pub width: u32,
+  pub height: u32,
+  pub dominant_color: String,
+  pub format: String,
 }
 
 pub struct Store {
@@ -73,29 +125,31 @@ impl Store {
 
   pub fn create_item_from_row(
     &self,
-    row: (i32, String, String, String, String, u32, u32),
+    row: ImageRow,
   ) -> Result<StoreDataItem, Box<dyn std::error::Error + '_>> {
-    let (id, url, placeholder, preview_type_str, cache_key, original_width, original_height) = row;
-    let preview_type = PlaceholderImageOutputKind::from_string(&preview_type_str);
+    let preview_type = PlaceholderImageOutputKind::from_string(&row.preview_type);
     Ok(StoreDataItem {
-      id,
-      url,
-      placeholder,
+      id: row.id,
+      url: row.url,
+      placeholder: row.placeholder,
       cache: true,
       preview_type,
-      cache_key,
+      cache_key: row.cache_key,
       db_action: DbAction::Skip,
-      original_width,
-      original_height,
+      original_width: row.original_width,
+      original_height: row.original_height,
+      trim_box: row.trim_box.and_then(|s| TrimBox::from_string(&s)),
+      tuning: row.tuning.and_then(|s| PlaceholderTuning::from_string(&s)),
+      similarity: row.similarity,
+      dominant_color: row.dominant_color,
+      format: row.format,
     })
   }
 
   pub fn insert_or_update(
     &self,
     url: String,
-    placeholder: String,
-    original_width: u32,
-    original_height: u32,
+    output: ProcessImageOutput,
     options: &PreviewOptions,
   ) -> Result<(), Box<dyn std::error::Error + '_>> {
     let mut map = self.data.lock()?;
@@ -106,7 +160,7 @@ impl Store {
     let item = StoreDataItem {
       id: existing_item.map_or(0, |item| item.id),
       url: url.clone(),
-      placeholder,
+      placeholder: output.base64_str,
       cache: options.cache,
       preview_type: options.output_kind.clone(),
       cache_key: Store::create_cache_key(options),
@@ -117,71 +171,63 @@ impl Store {
         }
         _ => DbAction::Insert,
       },
-      original_height,
-      original_width,
+      original_height: output.original_height,
+      original_width: output.original_width,
+      trim_box: output.trim_box,
+      tuning: output.tuning,
+      similarity: output.similarity,
+      // Kept when the image is processed again without its metadata
+      dominant_color: output
+        .dominant_color
+        .or_else(|| existing_item.and_then(|item| item.dominant_color.clone())),
+      format: Some(output.format),
     };
 
     map.insert(map_key, item);
     Ok(())
   }
 
-  #[allow(clippy::type_complexity)]
+  /// Returns the rows to insert and the rows to update, in that order.
   pub fn get_prepared_data(
     &self,
-  ) -> Result<
-    (
-      Vec<(String, String, String, String, u32, u32)>,
-      Vec<(i32, String, String, String, String, u32, u32)>,
-    ),
-    Box<dyn std::error::Error + '_>,
-  > {
+  ) -> Result<(Vec<ImageRow>, Vec<ImageRow>), Box<dyn std::error::Error + '_>> {
     let data_vecc: Vec<(String, StoreDataItem)> = {
       let map = self.data.lock()?;
       map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
     };
 
-    let mut to_insert: Vec<(String, String, String, String, u32, u32)> = vec![];
-    let mut to_update: Vec<(i32, String, String, String, String, u32, u32)> = vec![];
+    let mut to_insert: Vec<ImageRow> = vec![];
+    let mut to_update: Vec<ImageRow> = vec![];
 
     data_vecc.iter().for_each(|(_, data)| {
       if !data.cache {
         return;
       }
       if data.db_action == DbAction::Insert {
-        to_insert.push((
-          data.url.clone(),
-          data.placeholder.clone(),
-          PlaceholderImageOutputKind::get_string_name(&data.preview_type.clone()),
-          data.cache_key.clone(),
-          data.original_width,
-          data.original_height,
-        ));
+        to_insert.push(data.to_row());
       } else if data.db_action == DbAction::Update {
-        to_update.push((
-          data.id,
-          data.url.clone(),
-          data.placeholder.clone(),
-          PlaceholderImageOutputKind::get_string_name(&data.preview_type.clone()),
-          data.cache_key.clone(),
-          data.original_width,
-          data.original_height,
-        ));
+        to_update.push(data.to_row());
       }
     });
 
     Ok((to_insert, to_update))
   }
 
+  /// Whether the image was already processed with these options.
+  /// With `require_meta`, images cached before the metadata was stored count as missing.
   pub fn has_cached_image(
     &self,
     url: String,
     options: &PreviewOptions,
+    require_meta: bool,
   ) -> Result<bool, Box<dyn std::error::Error>> {
     let map = self.data.lock().unwrap();
     let cache_key = Store::create_cache_key(options);
     let item = map.get(format!("{}-{}", url, cache_key).as_str());
 
-    Ok(item.is_some())
+    Ok(item.is_some_and(|item| {
+      !require_meta || (item.dominant_color.is_some() && item.format.is_some())
+    }))
   }
 
   pub fn get_placeholder_from_url_and_options(
@@ -194,17 +240,23 @@ impl Store {
     if let Some(item) = map.get(format!("{}-{}", url, cache_key).as_str()) {
       if options.wrap_with_svg && options.output_kind != PlaceholderImageOutputKind::Blurred {
         let use_given_dimensions = options.width.is_some() && options.height.is_some();
+        let (source_width, source_height) = source_dimensions(
+          item.original_width,
+          item.original_height,
+          &item.trim_box,
+          options,
+        );
         return Ok(wrap_with_svg(
           item.placeholder.clone(),
           if use_given_dimensions {
             options.width.unwrap()
           } else {
-            item.original_width
+            source_width
           },
           if use_given_dimensions {
             options.height.unwrap()
           } else {
-            item.original_height
+            source_height
           },
         ));
       }
@@ -216,12 +268,75 @@ impl Store {
     )))
   }
 
+  pub fn get_meta_from_url_and_options(
+    &self,
+    url: String,
+    options: &PreviewOptions,
+  ) -> Result<PreviewMeta, Box<dyn std::error::Error + '_>> {
+    let placeholder = self.get_placeholder_from_url_and_options(url.clone(), options)?;
+
+    let map = self.data.lock()?;
+    let cache_key = Store::create_cache_key(options);
+    let item = map.get(format!("{}-{}", url, cache_key).as_str());
+    if let Some(item) = item {
+      if let (Some(dominant_color), Some(format)) = (&item.dominant_color, &item.format) {
+        // Dimensions of the rendition the preview stands for, so trimming isn't applied
+        let (width, height) =
+          transformed_dimensions(item.original_width, item.original_height, options);
+        return Ok(PreviewMeta {
+          placeholder,
+          width,
+          height,
+          dominant_color: dominant_color.clone(),
+          format: format.clone(),
+        });
+      }
+    }
+    Err(Box::new(std::io::Error::new(
+      std::io::ErrorKind::NotFound,
+      "Metadata not found for the given URL",
+    )))
+  }
+
   pub fn create_cache_key(options: &PreviewOptions) -> String {
-    format!(
+    let mut key = format!(
       "{}_{}_{}",
       options.output_kind.get_string_name(),
       options.width.unwrap_or(0),
       options.height.unwrap_or(0)
-    )
+    );
+
+    // Optional segments are only appended when enabled, so existing cache rows stay valid.
+    if let Some(crop) = &options.crop {
+      key.push_str(&format!("_crop{}", crop.get_string_name()));
+    }
+    if let Some(rotate) = options.rotate {
+      key.push_str(&format!("_rotate{}", rotate));
+    }
+    if let Some(flip) = &options.flip {
+      key.push_str(&format!("_flip{}", flip.get_string_name()));
+    }
+    if options.trim {
+      key.push_str(&format!("_trim{}", options.trim_tolerance));
+    }
+    if let Some(target_area) = options.target_area {
+      key.push_str(&format!("_area{}", target_area));
+    }
+    // The side bounds apply to every sizing mode
+    if options.min_side.is_some() || options.max_side.is_some() {
+      key.push_str(&format!(
+        "_min{}_max{}",
+        options.min_side.unwrap_or(0),
+        options.max_side.unwrap_or(0)
+      ));
+    }
+    if let Some(max_bytes) = options.max_bytes {
+      key.push_str(&format!("_bytes{}", max_bytes));
+    }
+    if let Some(target_similarity) = options.target_similarity {
+      key.push_str(&format!("_ssim{}", target_similarity));
+    }
+
+    key
   }
 }
@@ -1,74 +1,62 @@
-use bytes::Bytes;
-use futures::Future;
-#[cfg(not(target_arch = "wasm32"))]
-use futures::{stream::FuturesUnordered, StreamExt};
 use napi_derive::napi;
-use once_cell::sync::Lazy;
 use oxc::{
-  allocator::{Allocator, Box as OxcBox},
+  allocator::{Allocator, Box as OxcBox, CloneIn, TakeIn},
   ast::{
     ast::{
-      Argument, CallExpression, Expression, ImportDeclaration, ImportDeclarationSpecifier,
-      ModuleExportName, ObjectPropertyKind, Program, PropertyKey, SourceType, StringLiteral,
+      Argument, ArrayExpressionElement, BinaryOperator, BindingPatternKind, CallExpression,
+      Expression, FormalParameters, IdentifierReference, ImportDeclaration,
+      ImportDeclarationSpecifier, JSXAttribute, JSXAttributeItem, JSXAttributeValue,
+      JSXElementName, JSXExpression, JSXOpeningElement, LogicalOperator, NumberBase,
+      ObjectPropertyKind, Program, PropertyKey, PropertyKind, SourceType, Statement, StringLiteral,
+      UnaryOperator, VariableDeclarationKind, VariableDeclarator,
     },
-    AstBuilder,
+    AstBuilder, NONE,
   },
-  ast_visit::{walk_mut, VisitMut},
+  ast_visit::{walk_mut, Visit, VisitMut},
   codegen::{Codegen, CodegenOptions},
   parser::{Parser, ParserReturn},
   semantic::{Scoping, SemanticBuilder, SymbolId},
+  span::{GetSpan, Span, SPAN},
+  syntax::identifier::is_identifier_name,
 };
-use reqwest::Client;
 use rusqlite::Connection;
-#[cfg(target_arch = "wasm32")]
-use std::mem;
-#[cfg(target_arch = "wasm32")]
-use std::pin::Pin;
-#[cfg(not(target_arch = "wasm32"))]
-use std::time::Duration;
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
   path::PathBuf,
-  sync::{Arc, Mutex},
   time::Instant,
 };
-#[cfg(not(target_arch = "wasm32"))]
-use tokio::task::JoinHandle;
 use url::Url;
 
 use crate::{
-  log::{self, create_log, set_log_level, style_error, LogLevel},
-  placeholder_image::{download_and_process_image, process_image, PlaceholderImageOutputKind},
-  store::Store,
+  component::{ComponentKind, VirtualModule},
+  css::CssImageOptions,
+  directives::{apply_pragma, get_file_pragmas, get_ignored_positions},
+  evaluate::{ConstEvaluator, ConstObject, ConstValue},
+  html::HtmlImageOptions,
+  image_queue::ImageQueue,
+  log::{self, create_log, set_log_level, LogLevel},
+  markdown::MarkdownImageOptions,
+  placeholder_image::{
+    CropRect, CropUnit, FlipDirection, PlaceholderImageOutputKind, DEFAULT_TRIM_TOLERANCE,
+  },
+  resolve::{get_local_image_key, resolve_image_specifier, resolve_module_relative},
+  source_patch::SourcePatcher,
+  sourcemap::compose_input_sourcemap,
 };
 
-static HTTP_CLIENT: Lazy<Client> = Lazy::new(|| {
-  #[cfg(not(target_arch = "wasm32"))]
-  let mut builder = Client::builder();
-
-  #[cfg(target_arch = "wasm32")]
-  let builder = Client::builder();
-
-  #[cfg(not(target_arch = "wasm32"))]
-  {
-    builder = builder.timeout(Duration::from_secs(10));
-  }
-  // In CI or testing environments, be more permissive with certificates
-  #[cfg(not(target_arch = "wasm32"))]
-  if std::env::var("NOCOJS_DANGEROUSLY_ACCEPT_INVALID_SSL_CERTS").is_ok() {
-    builder = builder.danger_accept_invalid_certs(true);
-  }
-
-  builder.build().unwrap()
-});
-
 #[derive(PartialEq, Debug, Clone)]
 enum Pass {
   First,
   Second,
 }
 
-const IMPORT_PATH: &str = "@nocojs/client";
+const DEFAULT_IMPORT_SOURCE: &str = "@nocojs/client";
+const DEFAULT_FUNCTION_NAME: &str = "preview";
+const DEFAULT_META_FUNCTION_NAME: &str = "previewMeta";
+const DEFAULT_JSX_MARKER_ATTRIBUTE: &str = "data-nocojs";
+const DEFAULT_JSX_PLACEHOLDER_ATTRIBUTE: &str = "data-placeholder";
+const NEXT_IMAGE_SOURCES: &[&str] = &["next/image", "next/legacy/image"];
+const HOISTED_PLACEHOLDER_PREFIX: &str = "__noco_";
 
 #[napi(object)]
 #[derive(Clone, Debug)]
@@ -83,6 +71,141 @@ pub struct TransformOptions {
   pub height: Option<u32>,
   pub sourcemap_file_path: Option<String>,
   pub wrap_with_svg: Option<bool>,
+  pub trim: Option<bool>,
+  pub trim_tolerance: Option<u32>,
+  pub target_area: Option<u32>,
+  pub min_side: Option<u32>,
+  pub max_side: Option<u32>,
+  pub max_bytes: Option<u32>,
+  pub target_similarity: Option<f64>,
+  /// Splice the replacements into the original source instead of re-printing the whole module.
+  /// Comments and formatting are kept and the sourcemap is near-identity.
+  pub preserve_formatting: Option<bool>,
+  /// Sourcemap (JSON string) of the code passed to `transform`, e.g. from an earlier loader.
+  /// When set, the returned sourcemap is composed with it and points at the original sources.
+  pub input_sourcemap: Option<String>,
+  /// Modules whose functions are treated as "preview" calls.
+  /// Defaults to the "preview" function of "@nocojs/client".
+  pub import_sources: Option<Vec<ImportSource>>,
+  /// Values substituted for globals and member expressions when resolving preview arguments,
+  /// eg: `process.env.CDN_URL` or `import.meta.env.BASE_URL`.
+  pub define: Option<HashMap<String, String>>,
+  /// Path aliases for local images, eg: `{ "@/assets": "./src/assets" }`.
+  /// Relative targets are resolved against the working directory.
+  pub alias: Option<HashMap<String, String>>,
+  /// Public directories searched after `public_dir`, in order.
+  pub public_dirs: Option<Vec<String>>,
+  /// Enriches JSX image elements with their placeholder and dimensions.
+  /// Disabled unless set.
+  pub jsx_images: Option<JsxImageOptions>,
+  /// Injects `blurDataURL` into `next/image` elements using `placeholder="blur"`
+  /// with a remote or public `src`. Disabled unless set.
+  pub next_image_blur: Option<bool>,
+  /// Options of `transform_html`, which enriches the marked images of HTML documents.
+  pub html_images: Option<HtmlImageOptions>,
+  /// Options of `transform_css`, which replaces the images of stylesheets with their placeholder.
+  pub css_images: Option<CssImageOptions>,
+  /// Options of `transform_markdown`, which enriches the images of Markdown documents.
+  pub markdown_images: Option<MarkdownImageOptions>,
+}
+
+/// Enrichment of JSX image elements, eg: `<img src="/a.jpg" data-nocojs />`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct JsxImageOptions {
+  /// Elements with this attribute are enriched, the attribute is then removed.
+  /// Defaults to "data-nocojs".
+  pub marker_attribute: Option<String>,
+  /// Element or component names enriched without the marker attribute, eg: `["Image"]`.
+  pub components: Option<Vec<String>>,
+  /// Attribute receiving the placeholder. Defaults to "data-placeholder".
+  /// With "style", the placeholder becomes the background image of the element.
+  pub placeholder_attribute: Option<String>,
+}
+
+impl JsxImageOptions {
+  fn marker_attribute(&self) -> &str {
+    self
+      .marker_attribute
+      .as_deref()
+      .unwrap_or(DEFAULT_JSX_MARKER_ATTRIBUTE)
+  }
+
+  fn placeholder_attribute(&self) -> &str {
+    self
+      .placeholder_attribute
+      .as_deref()
+      .unwrap_or(DEFAULT_JSX_PLACEHOLDER_ATTRIBUTE)
+  }
+
+  /// Whether the code may contain elements to enrich.
+  fn may_match(&self, code: &str) -> bool {
+    code.contains(self.marker_attribute())
+      || self
+        .components
+        .iter()
+        .flatten()
+        .any(|name| code.contains(&format!("<{}", name)))
+  }
+}
+
+/// A module exporting preview functions, eg: a design system re-exporting "preview" helpers.
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct ImportSource {
+  pub source: String,
+  /// Exported function names mapped to the preset applied to their calls.
+  pub names: HashMap<String, PreviewPreset>,
+}
+
+/// Preview options applied to every call of a function from `import_sources`.
+/// Options passed to the call itself take precedence.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct PreviewPreset {
+  pub width: Option<u32>,
+  pub height: Option<u32>,
+  pub placeholder_type: Option<PlaceholderImageOutputKind>,
+  pub replace_function_call: Option<bool>,
+  pub cache: Option<bool>,
+  pub wrap_with_svg: Option<bool>,
+  pub trim: Option<bool>,
+  pub trim_tolerance: Option<u32>,
+  pub crop: Option<CropRect>,
+  pub rotate: Option<u32>,
+  pub flip: Option<FlipDirection>,
+  pub target_area: Option<u32>,
+  pub min_side: Option<u32>,
+  pub max_side: Option<u32>,
+  pub max_bytes: Option<u32>,
+  pub target_similarity: Option<f64>,
+  /// Replace calls with an object of image metadata
+  /// (`{ placeholder, width, height, aspectRatio, dominantColor, format }`) instead of the placeholder.
+  pub meta: Option<bool>,
+}
+
+impl PreviewPreset {
+  fn is_meta(&self) -> bool {
+    self.meta.unwrap_or(false)
+  }
+}
+
+impl ImportSource {
+  fn default_sources() -> Vec<ImportSource> {
+    vec![ImportSource {
+      source: DEFAULT_IMPORT_SOURCE.to_string(),
+      names: HashMap::from([
+        (DEFAULT_FUNCTION_NAME.to_string(), PreviewPreset::default()),
+        (
+          DEFAULT_META_FUNCTION_NAME.to_string(),
+          PreviewPreset {
+            meta: Some(true),
+            ..PreviewPreset::default()
+          },
+        ),
+      ]),
+    }]
+  }
 }
 
 #[napi(object)]
@@ -101,6 +224,16 @@ pub struct PreviewOptions {
   pub replace_function_call: bool,
   pub cache: bool,
   pub wrap_with_svg: bool,
+  pub trim: bool,
+  pub trim_tolerance: u32,
+  pub crop: Option<CropRect>,
+  pub rotate: Option<u32>,
+  pub flip: Option<FlipDirection>,
+  pub target_area: Option<u32>,
+  pub min_side: Option<u32>,
+  pub max_side: Option<u32>,
+  pub max_bytes: Option<u32>,
+  pub target_similarity: Option<f64>,
 }
 
 impl PreviewOptions {
@@ -115,93 +248,150 @@ impl PreviewOptions {
       replace_function_call: options.replace_function_call.unwrap_or(true),
       cache: options.cache.unwrap_or(true),
       wrap_with_svg: options.wrap_with_svg.unwrap_or(true),
+      trim: options.trim.unwrap_or(false),
+      trim_tolerance: options.trim_tolerance.unwrap_or(DEFAULT_TRIM_TOLERANCE),
+      crop: None,
+      rotate: None,
+      flip: None,
+      target_area: options.target_area,
+      min_side: options.min_side,
+      max_side: options.max_side,
+      max_bytes: options.max_bytes,
+      target_similarity: options.target_similarity,
     }
   }
-}
 
-pub static RUSQLITE_FILE_NAME: &str = "cache.db";
-
-#[cfg(target_arch = "wasm32")]
-fn spawn_task<F>(fut: F) -> std::pin::Pin<Box<dyn Future<Output = ()> + 'static>>
-where
-  F: Future<Output = ()> + 'static,
-{
-  Box::pin(fut)
+  /// Overrides the options set in `preset`.
+  pub fn apply_preset(&mut self, preset: &PreviewPreset) {
+    if preset.width.is_some() || preset.height.is_some() {
+      self.width = preset.width;
+      self.height = preset.height;
+    }
+    if let Some(placeholder_type) = &preset.placeholder_type {
+      self.output_kind = placeholder_type.clone();
+    }
+    if let Some(replace_function_call) = preset.replace_function_call {
+      self.replace_function_call = replace_function_call;
+    }
+    if let Some(cache) = preset.cache {
+      self.cache = cache;
+    }
+    if let Some(wrap_with_svg) = preset.wrap_with_svg {
+      self.wrap_with_svg = wrap_with_svg;
+    }
+    if let Some(trim) = preset.trim {
+      self.trim = trim;
+    }
+    if let Some(trim_tolerance) = preset.trim_tolerance {
+      self.trim_tolerance = trim_tolerance;
+    }
+    if preset.crop.is_some() {
+      self.crop = preset.crop.clone();
+    }
+    if preset.rotate.is_some() {
+      self.rotate = preset.rotate;
+    }
+    if preset.flip.is_some() {
+      self.flip = preset.flip.clone();
+    }
+    if preset.target_area.is_some() {
+      self.target_area = preset.target_area;
+    }
+    if preset.min_side.is_some() {
+      self.min_side = preset.min_side;
+    }
+    if preset.max_side.is_some() {
+      self.max_side = preset.max_side;
+    }
+    if preset.max_bytes.is_some() {
+      self.max_bytes = preset.max_bytes;
+    }
+    if preset.target_similarity.is_some() {
+      self.target_similarity = preset.target_similarity;
+    }
+  }
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-fn spawn_task<F>(fut: F) -> tokio::task::JoinHandle<()>
-where
-  F: Future<Output = ()> + Send + 'static,
-{
-  tokio::spawn(fut)
-}
+pub static RUSQLITE_FILE_NAME: &str = "cache.db";
 
 pub async fn transform(
   code: String,
   file_path: String,
   options: TransformOptions,
 ) -> Result<Option<TransformOutput>, Box<dyn std::error::Error>> {
-  if !code.contains(IMPORT_PATH) {
+  let import_sources = options
+    .import_sources
+    .clone()
+    .unwrap_or_else(ImportSource::default_sources);
+
+  let has_jsx_images = options
+    .jsx_images
+    .as_ref()
+    .is_some_and(|jsx_images| jsx_images.may_match(&code));
+
+  let has_next_images = options.next_image_blur.unwrap_or(false)
+    && NEXT_IMAGE_SOURCES
+      .iter()
+      .any(|source| code.contains(source));
+
+  if !has_jsx_images
+    && !has_next_images
+    && !import_sources
+      .iter()
+      .any(|import_source| code.contains(&import_source.source))
+  {
     return Ok(None);
   }
 
   let instant = Instant::now();
 
-  if options.log_level.is_some() {
-    set_log_level(options.log_level.unwrap());
+  if let Some(log_level) = options.log_level {
+    set_log_level(log_level);
   }
 
-  let cache_dir = init_cache_dir(
-    &options
-      .cache_file_dir
-      .clone()
-      .unwrap_or(".nocojs".to_string()),
-  )
-  .unwrap_or("".to_string());
+  let allocator = Allocator::default();
+  // Eg: App.vue, whose scripts and markup expressions are transformed as a virtual module.
+  // Components already compiled to JavaScript by an earlier plugin are transformed as TypeScript.
+  let component_kind = ComponentKind::from_path(&file_path);
+  let component = component_kind.and_then(|kind| VirtualModule::new(&code, kind));
+  let (source_text, source_type) = match (&component, component_kind) {
+    (Some(module), _) => (module.code.as_str(), module.source_type),
+    (None, Some(_)) => (code.as_str(), SourceType::ts()),
+    (None, None) => (code.as_str(), SourceType::from_path(&file_path)?),
+  };
 
-  let db_filepath = PathBuf::from(&cache_dir).join(RUSQLITE_FILE_NAME);
-  let conn = Connection::open(&db_filepath);
+  let sourcemap_file_path = options.sourcemap_file_path.clone();
+  let sourcemap_file_path = sourcemap_file_path.unwrap_or_else(|| file_path.clone());
 
-  let conn = match conn {
-    Ok(c) => Some(c),
-    Err(e) => {
+  let ParserReturn { mut program, .. } = Parser::new(&allocator, source_text, source_type).parse();
+
+  // Eg: // @nocojs placeholderType=blurred width=24
+  let code_start = program
+    .directives
+    .first()
+    .map(|directive| directive.span.start)
+    .or_else(|| program.body.first().map(|statement| statement.span().start))
+    .unwrap_or(program.span.end);
+  let mut options = options;
+  for pragma in get_file_pragmas(&program.comments, source_text, code_start) {
+    if let Err(e) = apply_pragma(&mut options, pragma) {
       create_log(
         log::style_error(format!(
-          "Failed to open sqlite database at {:?}. Persistent caching won't work. Error: {}",
-          db_filepath, e
+          "Invalid @nocojs pragma: {}. File: {}",
+          e, file_path
         )),
         LogLevel::Error,
       );
-      None
     }
-  };
-
-  let _ = setup_sqlite(&conn);
-
-  let allocator = Allocator::default();
-  let source_type = SourceType::from_path(&file_path)?;
-
-  let sourcemap_file_path = options.sourcemap_file_path.clone();
-  let sourcemap_file_path = sourcemap_file_path.unwrap_or_else(|| file_path.clone());
-
-  let ParserReturn { mut program, .. } = Parser::new(&allocator, &code, source_type).parse();
+  }
+  let ignored_positions = get_ignored_positions(&program.comments, source_text);
 
   let semantic_builder = SemanticBuilder::new().build(&program);
 
   let scoping = semantic_builder.semantic.into_scoping();
 
   let ast_builder = AstBuilder::new(&allocator);
-  let util_import_symbols: Vec<SymbolId> = vec![];
-
-  #[cfg(not(target_arch = "wasm32"))]
-  let tasks = FuturesUnordered::new();
-
-  #[cfg(target_arch = "wasm32")]
-  let tasks = vec![];
-
-  let store_data = Arc::new(Mutex::new(HashMap::new()));
-  let store = Arc::new(Store { data: store_data });
+  let util_import_symbols: HashMap<SymbolId, PreviewPreset> = HashMap::new();
 
   let has_changes = false;
   // Traverse the AST
@@ -211,33 +401,52 @@ pub async fn transform(
     scoping: &scoping,
     pass: Pass::First,
     util_import_symbols,
-    rusqlite_conn: conn,
-    tasks,
+    util_namespace_symbols: HashMap::new(),
+    next_image_symbols: vec![],
+    import_sources,
+    evaluator: ConstEvaluator::new(&scoping, options.define.clone().unwrap_or_default()),
+    images: ImageQueue::new(&options, &file_path),
     options: options.clone(),
-    store: Arc::clone(&store),
     has_changes,
     file_path: file_path.clone(),
+    code: &code,
+    component: component.as_ref(),
+    ignored_positions,
+    ignored_depth: 0,
+    hoisted_placeholders: vec![],
+    hoist_placeholders: component.is_none(),
+    single_quote: component.is_some(),
+    patcher: (options.preserve_formatting.unwrap_or(false) || component.is_some())
+      .then(SourcePatcher::new),
   };
 
   visitor.begin(&mut program).await;
 
-  let codegen = Codegen::new();
-  let codegen = codegen.with_options(CodegenOptions {
-    source_map_path: Some(PathBuf::from(&sourcemap_file_path)),
-    ..CodegenOptions::default()
-  });
-  let result = codegen.build(&program);
-
-  let result_code: String = result.code;
+  let (result_code, sourcemap) = match &visitor.patcher {
+    Some(patcher) => {
+      let remapped = component.as_ref().map(|module| module.remap(patcher));
+      let (patched_code, map) = remapped
+        .as_ref()
+        .unwrap_or(patcher)
+        .apply(&code, &sourcemap_file_path);
+      (patched_code, Some(map))
+    }
+    None => {
+      let codegen = Codegen::new();
+      let codegen = codegen.with_options(CodegenOptions {
+        source_map_path: Some(PathBuf::from(&sourcemap_file_path)),
+        ..CodegenOptions::default()
+      });
+      let result = codegen.build(&program);
 
-  let sourcemap: Option<String> = {
-    if result.map.is_some() {
-      Some(result.map.unwrap().to_json_string())
-    } else {
-      None
+      (result.code, result.map)
     }
   };
 
+  let sourcemap = sourcemap
+    .map(|map| compose_input_sourcemap(map, options.input_sourcemap.as_deref(), &file_path))
+    .map(|map| map.to_json_string());
+
   let transform_result = Some(TransformOutput {
     code: result_code,
     sourcemap,
@@ -273,6 +482,11 @@ pub fn setup_sqlite(connection: &Option<Connection>) -> Result<(), Box<dyn std::
           cache_key TEXT NOT NULL,
           original_width INTEGER NOT NULL,
           original_height INTEGER NOT NULL,
+          trim_box TEXT,
+          tuning TEXT,
+          similarity REAL,
+          dominant_color TEXT,
+          format TEXT,
           UNIQUE(url, cache_key)
       )",
     [],
@@ -315,6 +529,46 @@ pub fn setup_sqlite(connection: &Option<Connection>) -> Result<(), Box<dyn std::
     )?;
   }
 
+  migrate_sqlite(conn)?;
+
+  Ok(())
+}
+
+/// Columns added to the images table after the initial release.
+/// They are added to existing databases so that old caches keep working.
+const IMAGES_TABLE_MIGRATIONS: &[(&str, &str)] = &[
+  ("trim_box", "TEXT"),
+  ("tuning", "TEXT"),
+  ("similarity", "REAL"),
+  ("dominant_color", "TEXT"),
+  ("format", "TEXT"),
+];
+
+fn migrate_sqlite(conn: &Connection) -> Result<(), Box<dyn std::error::Error>> {
+  let mut stmt = conn.prepare("PRAGMA table_info(images)")?;
+  let existing_columns = stmt
+    .query_map([], |row| row.get::<_, String>(1))?
+    .collect::<Result<Vec<_>, _>>()?;
+
+  for (column, column_type) in IMAGES_TABLE_MIGRATIONS {
+    if existing_columns.iter().any(|c| c == column) {
+      continue;
+    }
+
+    if let Err(e) = conn.execute(
+      &format!("ALTER TABLE images ADD COLUMN {} {}", column, column_type),
+      [],
+    ) {
+      create_log(
+        log::style_error(format!(
+          "Failed to add column {} to the images table. Error: {}",
+          column, e
+        )),
+        LogLevel::Error,
+      );
+    }
+  }
+
   Ok(())
 }
 
@@ -335,116 +589,396 @@ pub fn init_cache_dir(dirname: &str) -> Result<String, Box<dyn std::error::Error
   Ok(dirname.to_string())
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-type TasksType<T> = FuturesUnordered<JoinHandle<T>>;
+/// Collects the symbols referenced in a program.
+struct ReferencedSymbols<'s> {
+  scoping: &'s Scoping,
+  symbols: HashSet<SymbolId>,
+}
+
+impl<'a> Visit<'a> for ReferencedSymbols<'_> {
+  fn visit_identifier_reference(&mut self, it: &IdentifierReference<'a>) {
+    let symbol_id = it
+      .reference_id
+      .get()
+      .and_then(|reference_id| self.scoping.get_reference(reference_id).symbol_id());
+    if let Some(symbol_id) = symbol_id {
+      self.symbols.insert(symbol_id);
+    }
+  }
+}
+
+/// Prints an import declaration whose specifiers were changed.
+/// The source and the import attributes are copied from `source_text`.
+fn print_import_declaration(declaration: &ImportDeclaration, source_text: &str) -> String {
+  let mut default = None;
+  let mut namespace = None;
+  let mut named = vec![];
+
+  for specifier in declaration.specifiers.iter().flatten() {
+    match specifier {
+      ImportDeclarationSpecifier::ImportDefaultSpecifier(specifier) => {
+        default = Some(specifier.local.name.to_string());
+      }
+      ImportDeclarationSpecifier::ImportNamespaceSpecifier(specifier) => {
+        namespace = Some(format!("* as {}", specifier.local.name));
+      }
+      ImportDeclarationSpecifier::ImportSpecifier(specifier) => {
+        let imported = specifier.imported.name();
+        let kind = if specifier.import_kind.is_type() {
+          "type "
+        } else {
+          ""
+        };
+        named.push(match imported == specifier.local.name.as_str() {
+          true => format!("{}{}", kind, imported),
+          false if is_identifier_name(&imported) => {
+            format!("{}{} as {}", kind, imported, specifier.local.name)
+          }
+          false => format!("{}{:?} as {}", kind, imported, specifier.local.name),
+        });
+      }
+    }
+  }
+
+  let mut clauses = default.into_iter().collect::<Vec<_>>();
+  clauses.extend(namespace);
+  if !named.is_empty() {
+    clauses.push(format!("{{ {} }}", named.join(", ")));
+  }
+
+  let kind = if declaration.import_kind.is_type() {
+    "type "
+  } else {
+    ""
+  };
+  let source = declaration.source.span.source_text(source_text);
+  let with_clause = declaration
+    .with_clause
+    .as_ref()
+    .map(|with_clause| format!(" {}", with_clause.span.source_text(source_text)))
+    .unwrap_or_default();
+
+  format!(
+    "import {}{} from {}{};",
+    kind,
+    clauses.join(", "),
+    source,
+    with_clause
+  )
+}
+
+fn print_expression(expr: &Expression, single_quote: bool) -> String {
+  let mut codegen = Codegen::new().with_options(CodegenOptions {
+    single_quote,
+    ..CodegenOptions::default()
+  });
+  codegen.print_expression(expr);
+  codegen.into_source_text()
+}
+
+/// Finds an attribute of a JSX element by name, eg: `src` or `data-nocojs`.
+fn get_jsx_attribute<'e, 'a>(
+  element: &'e JSXOpeningElement<'a>,
+  name: &str,
+) -> Option<&'e JSXAttribute<'a>> {
+  element.attributes.iter().find_map(|attr| match attr {
+    JSXAttributeItem::Attribute(attr) if attr.is_identifier(name) => Some(&**attr),
+    _ => None,
+  })
+}
+
+/// Returns the parameters and the returned expression of a callback,
+/// eg: `(item, index) => expression` or `function (item) { return expression; }`.
+fn get_callback_body<'e, 'a>(
+  callback: &'e Expression<'a>,
+) -> Option<(&'e FormalParameters<'a>, &'e Expression<'a>)> {
+  let (params, statements) = match callback {
+    Expression::ArrowFunctionExpression(arrow) => {
+      if let Some(expression) = arrow.get_expression() {
+        return Some((&arrow.params, expression));
+      }
+      (&arrow.params, &arrow.body.statements)
+    }
+    Expression::FunctionExpression(function) => {
+      (&function.params, &function.body.as_ref()?.statements)
+    }
+    _ => return None,
+  };
+
+  match statements.as_slice() {
+    [Statement::ReturnStatement(return_statement)] => {
+      Some((&**params, return_statement.argument.as_ref()?))
+    }
+    _ => None,
+  }
+}
+
+/// Returns the module specifier if the expression loads a module,
+/// ie: `require('@nocojs/client')` or `await import('@nocojs/client')`.
+fn get_module_expression_source<'e>(expr: &'e Expression) -> Option<&'e str> {
+  match expr.without_parentheses() {
+    Expression::CallExpression(call) if call.is_require_call() => match call.arguments.first() {
+      Some(Argument::StringLiteral(source)) => Some(source.value.as_str()),
+      _ => None,
+    },
+    Expression::AwaitExpression(await_expr) => match await_expr.argument.without_parentheses() {
+      Expression::ImportExpression(import_expr) => match &import_expr.source {
+        Expression::StringLiteral(source) => Some(source.value.as_str()),
+        _ => None,
+      },
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+/// Whether the right operand of a logical expression is a fallback image URL, ie: `||` and `??`.
+/// The left operand of `&&` is only tested, never used as the URL.
+fn is_url_fallback(operator: LogicalOperator) -> bool {
+  matches!(operator, LogicalOperator::Or | LogicalOperator::Coalesce)
+}
 
-#[cfg(target_arch = "wasm32")]
-type TasksType<T> = Vec<Pin<Box<dyn Future<Output = T>>>>;
+/// Whether an expression is a reference that can be evaluated twice, eg: `src` or `this.props.src`.
+fn is_plain_reference(expr: &Expression) -> bool {
+  match expr.without_parentheses() {
+    Expression::Identifier(_) | Expression::ThisExpression(_) => true,
+    Expression::StaticMemberExpression(member) => is_plain_reference(&member.object),
+    _ => false,
+  }
+}
 
 struct TransformVisitor<'a> {
   allocator: &'a Allocator,
   ast_builder: AstBuilder<'a>,
   scoping: &'a Scoping,
-  util_import_symbols: Vec<SymbolId>,
+  /// Bindings of the preview functions, with the preset of their import source.
+  util_import_symbols: HashMap<SymbolId, PreviewPreset>,
+  /// Bindings of the whole module, eg: `import * as noco` or `const noco = require(...)`,
+  /// with the index of their import source.
+  util_namespace_symbols: HashMap<SymbolId, usize>,
+  /// Bindings of the `next/image` component, eg: `import Image from 'next/image'`.
+  next_image_symbols: Vec<SymbolId>,
+  import_sources: Vec<ImportSource>,
   pass: Pass,
-  rusqlite_conn: Option<Connection>,
-  tasks: TasksType<()>,
+  images: ImageQueue,
   options: TransformOptions,
   has_changes: bool,
-  store: Arc<Store>,
   file_path: String,
+  /// The code of the file, to report the line and column of problems.
+  code: &'a str,
+  /// The component the transformed code was extracted from, if any.
+  component: Option<&'a VirtualModule>,
+  /// Starts of the statements and calls following a `@nocojs-ignore` comment.
+  ignored_positions: HashSet<u32>,
+  /// Number of ignored statements the visitor is in.
+  ignored_depth: u32,
+  /// Placeholders used more than once in the file, with the name of the constant holding them.
+  hoisted_placeholders: Vec<(String, String)>,
+  /// Disabled for components, whose markup can't always reference the bindings of their scripts.
+  hoist_placeholders: bool,
+  /// Prints the patches with single quotes, eg: for Vue attribute bindings.
+  single_quote: bool,
+  /// Collects the replacements when `preserve_formatting` is enabled.
+  patcher: Option<SourcePatcher>,
+  evaluator: ConstEvaluator<'a>,
 }
 
 impl<'a> TransformVisitor<'a> {
   ///
-  /// Begins the transformation. It first initializes the store from the database,
-  /// Then attemps a two-pass transformation.
+  /// Begins the transformation. The store was initialized from the database when the image queue was created.
+  /// It attemps a two-pass transformation.
   /// The first pass identifies function calls that need to be replaced with placeholder images.
   /// It then spawns tasks to process images asynchronously.
   ///
   /// If there are changes, it first pushes the new data to the db and begins the second pass,
   /// which replaces the function calls with the processed image URLs.
   ///
-  #[allow(clippy::redundant_pattern_matching)]
   async fn begin(&mut self, program: &mut Program<'a>) {
-    let _ = self.set_store_data_from_db();
-
     self.visit_program(program);
 
     self.pass = Pass::Second;
 
     if self.has_changes {
-      #[cfg(target_arch = "wasm32")]
-      {
-        let tasks = mem::take(&mut self.tasks); // replace with empty Vec
+      self.images.finish().await;
 
-        for task in tasks {
-          let _ = task.await;
-        }
+      if self.hoist_placeholders {
+        self.set_hoisted_placeholders();
       }
+      self.visit_program(program);
+      self.remove_unused_imports(program);
+      self.insert_hoisted_placeholders(program);
+    }
+  }
 
-      #[cfg(not(target_arch = "wasm32"))]
-      while let Some(_) = self.tasks.next().await {}
+  /// Names the placeholders used more than once in the file, eg: a fallback image in a list.
+  /// Each of them is declared once as `const __noco_0 = "data:..."` and referenced by the calls.
+  fn set_hoisted_placeholders(&mut self) {
+    let mut placeholders: Vec<(String, usize)> = vec![];
+    for (url, options, require_meta) in &self.images.queued_images {
+      let placeholder = if *require_meta {
+        self
+          .images
+          .store
+          .get_meta_from_url_and_options(url.clone(), options)
+          .map(|meta| meta.placeholder)
+      } else {
+        self
+          .images
+          .store
+          .get_placeholder_from_url_and_options(url.clone(), options)
+      };
+      let Ok(placeholder) = placeholder else {
+        continue;
+      };
 
-      let _ = self.push_store_data_to_db();
+      match placeholders.iter_mut().find(|(p, _)| *p == placeholder) {
+        Some((_, count)) => *count += 1,
+        None => placeholders.push((placeholder, 1)),
+      }
+    }
 
-      self.visit_program(program);
+    let mut names = (0..).map(|index| format!("{}{}", HOISTED_PLACEHOLDER_PREFIX, index));
+    for (placeholder, count) in placeholders {
+      if count < 2 {
+        continue;
+      }
+      // Skips the names already used by the file
+      let Some(name) = names.find(|name| {
+        !self.scoping.symbol_names().any(|symbol| symbol == name)
+          && !self
+            .scoping
+            .root_unresolved_references()
+            .contains_key(name.as_str())
+      }) else {
+        continue;
+      };
+      self.hoisted_placeholders.push((placeholder, name));
     }
   }
 
-  /// Reads the existing data from the database and populates the store.
-  /// This is called at the beginning of the transformation to ensure that any existing cached images
-  /// are available for the transformation process.
-  fn set_store_data_from_db(&mut self) -> Result<(), Box<dyn std::error::Error + '_>> {
-    let conn = match &self.rusqlite_conn {
-      Some(c) => c,
-      None => {
-        return Ok(());
-      }
+  /// Declares the hoisted placeholders after the imports of the program.
+  fn insert_hoisted_placeholders(&mut self, program: &mut Program<'a>) {
+    if self.hoisted_placeholders.is_empty() {
+      return;
+    }
+
+    let index = program
+      .body
+      .iter()
+      .position(|statement| !matches!(statement, Statement::ImportDeclaration(_)))
+      .unwrap_or(program.body.len());
+    // Eg: after `import { render } from './render';`, or before the first statement without imports
+    let (insert_at, after_import) = match index.checked_sub(1) {
+      Some(last_import) => (program.body[last_import].span().end, true),
+      None => (
+        program
+          .body
+          .first()
+          .map_or(program.span.end, |statement| statement.span().start),
+        false,
+      ),
     };
 
-    let mut stmt = conn.prepare("SELECT id, url, placeholder, preview_type, cache_key, original_width, original_height FROM images")?;
-
-    let rows = stmt.query_map([], |row| {
-      Ok((
-        row.get::<_, i32>(0)?,
-        row.get::<_, String>(1)?,
-        row.get::<_, String>(2)?,
-        row.get::<_, String>(3)?,
-        row.get::<_, String>(4)?,
-        row.get::<_, u32>(5)?,
-        row.get::<_, u32>(6)?,
-      ))
-    })?;
-
-    let mut to_insert = vec![];
-    for row in rows {
-      let (id, url, placeholder, preview_type_str, cache_key, original_width, original_height) =
-        row?;
-      to_insert.push(self.store.create_item_from_row((
+    let mut declarations = vec![];
+    for (offset, (placeholder, name)) in self.hoisted_placeholders.iter().enumerate() {
+      let init = self.create_string_literal(placeholder, SPAN);
+      declarations.push(format!(
+        "const {} = {};",
+        name,
+        print_expression(&init, self.single_quote)
+      ));
+
+      let id = self.ast_builder.binding_pattern(
+        self
+          .ast_builder
+          .binding_pattern_kind_binding_identifier(SPAN, self.ast_builder.atom(name)),
+        NONE,
+        false,
+      );
+      let declarator = self.ast_builder.variable_declarator(
+        SPAN,
+        VariableDeclarationKind::Const,
         id,
-        url,
-        placeholder,
-        preview_type_str,
-        cache_key,
-        original_width,
-        original_height,
-      ))?);
+        Some(init),
+        false,
+      );
+      let declaration = self.ast_builder.declaration_variable(
+        SPAN,
+        VariableDeclarationKind::Const,
+        self.ast_builder.vec1(declarator),
+        false,
+      );
+      program
+        .body
+        .insert(index + offset, Statement::from(declaration));
     }
 
-    if !to_insert.is_empty() {
-      create_log(
-        log::style_info(format!(
-          "Loaded {} cached images from the database",
-          to_insert.len()
-        )),
-        LogLevel::Verbose,
-      );
+    if let Some(patcher) = &mut self.patcher {
+      let declarations = declarations.join("\n");
+      if after_import {
+        patcher.insert(insert_at, format!("\n{}", declarations));
+      } else {
+        patcher.insert(insert_at, format!("{}\n", declarations));
+      }
     }
+  }
+
+  /// Removes the imports of preview functions that are no longer referenced once their calls
+  /// were replaced, eg: `import { preview } from '@nocojs/client';`.
+  /// Declarations left without specifiers are removed as a whole.
+  /// Imports still used, eg: by a call that couldn't be resolved, are kept.
+  fn remove_unused_imports(&mut self, program: &mut Program<'a>) {
+    let mut referenced = ReferencedSymbols {
+      scoping: self.scoping,
+      symbols: HashSet::new(),
+    };
+    referenced.visit_program(program);
 
-    self.store.bulk_insert(to_insert)?;
+    let is_unused = |specifier: &ImportDeclarationSpecifier| {
+      let symbol_id = specifier.local().symbol_id();
+      (self.util_import_symbols.contains_key(&symbol_id)
+        || self.util_namespace_symbols.contains_key(&symbol_id))
+        && !referenced.symbols.contains(&symbol_id)
+    };
 
-    Ok(())
+    let source_text = program.source_text;
+    let mut patches = vec![];
+    program.body.retain_mut(|statement| {
+      let Statement::ImportDeclaration(declaration) = statement else {
+        return true;
+      };
+      let Some(specifiers) = &mut declaration.specifiers else {
+        return true;
+      };
+      if !specifiers.iter().any(is_unused) {
+        return true;
+      }
+
+      specifiers.retain(|specifier| !is_unused(specifier));
+      if !specifiers.is_empty() {
+        patches.push((
+          declaration.span,
+          print_import_declaration(declaration, source_text),
+        ));
+        return true;
+      }
+
+      // The line break after the declaration is removed with it
+      let mut span = declaration.span;
+      let rest = &source_text[span.end as usize..];
+      span.end += rest
+        .strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))
+        .map_or(0, |stripped| (rest.len() - stripped.len()) as u32);
+      patches.push((span, String::new()));
+      false
+    });
+
+    if let Some(patcher) = &mut self.patcher {
+      for (span, text) in patches {
+        patcher.replace(span, text);
+      }
+    }
   }
 
   /// Function used to process "preview" function calls.
@@ -456,34 +990,36 @@ impl<'a> TransformVisitor<'a> {
   fn prepare_image_from_fn_call(
     &mut self,
     call: &mut OxcBox<'a, CallExpression<'a>>,
+    preset: &PreviewPreset,
   ) -> Result<(), Box<dyn std::error::Error + '_>> {
     let first_arg = &call.arguments.first();
     let user_options_arg = &call.arguments.get(1);
 
-    let preview_options = self.get_preview_options_from_argument(user_options_arg);
-
     if let Some(image_url) = first_arg {
-      if let Expression::StringLiteral(string_value) = image_url.as_expression().unwrap() {
-        let url = string_value.value.to_string();
-        self.has_changes = true;
-
-        let exists_in_cache = { self.store.has_cached_image(url.clone(), &preview_options)? };
+      // Conditional and logical arguments have an URL for each branch
+      let urls = match image_url.as_expression() {
+        Some(expr) => self.get_image_urls_from_expression(expr),
+        None => vec![Err(image_url.span())],
+      };
+      if !urls.iter().any(Result::is_ok) {
+        let span = urls.into_iter().find_map(Result::err);
+        self.report_unresolved("the image URL", span.unwrap_or(image_url.span()));
+        return Err("Unresolved image URL in the function call".into());
+      }
 
-        if exists_in_cache {
-          create_log(
-            log::style_info(format!("Cache hit for {}", url)),
-            LogLevel::Verbose,
-          );
-          return Ok(());
-        }
+      let preview_options = self.get_preview_options_from_argument(user_options_arg, preset);
+      self.has_changes = true;
 
-        self.spawn_image_resize(url, preview_options);
+      for url in urls.into_iter().flatten() {
+        self
+          .images
+          .queue_image(url, preview_options.clone(), preset.is_meta())?;
       }
     } else {
       create_log(
         log::style_error(format!(
-          "No image URL provided in the function call. File: {}:{}",
-          self.file_path, call.span.start
+          "No image URL provided in the function call. File: {}",
+          self.get_location(call.span)
         )),
         LogLevel::Error,
       );
@@ -493,295 +1029,1149 @@ impl<'a> TransformVisitor<'a> {
     Ok(())
   }
 
-  /// Pushes the current state of the store to the database.
-  /// It prepares the data for insertion and/or update based on the current state of the store.
-  /// Function called after both passes of the transformation.
-  fn push_store_data_to_db(&mut self) -> Result<(), Box<dyn std::error::Error + '_>> {
-    if !self.store.has_changes()? {
-      return Ok(());
+  /// Precomputes `CONST_ARRAY.map(item => ...)` when the callback calls a preview function,
+  /// eg: `GALLERY.map(src => preview(src))` or `GALLERY.map(p => ({ ...p, ph: preview(p.src) }))`.
+  /// The callback is evaluated for every item of the const array. In the first pass the images are
+  /// queued, in the second pass the result holds the placeholders.
+  /// Returns None when the call doesn't match this pattern.
+  fn evaluate_static_map(&mut self, call: &CallExpression<'a>) -> Option<Result<ConstValue, Span>> {
+    let Expression::StaticMemberExpression(member) = &call.callee else {
+      return None;
+    };
+    if member.property.name != "map" || call.arguments.len() != 1 {
+      return None;
     }
+    let Ok(ConstValue::Array(items)) = self.evaluator.evaluate(&member.object) else {
+      return None;
+    };
 
-    let conn = match &mut self.rusqlite_conn {
-      Some(c) => c,
-      None => {
-        return Ok(());
+    let (params, body) = get_callback_body(call.arguments.first()?.as_expression()?)?;
+    let item_symbol = params
+      .items
+      .first()?
+      .pattern
+      .get_binding_identifier()?
+      .symbol_id();
+    let index_symbol = params
+      .items
+      .get(1)
+      .and_then(|param| param.pattern.get_binding_identifier())
+      .map(|identifier| identifier.symbol_id());
+
+    let mut found_preview = false;
+    let mut values = vec![];
+
+    for (index, item) in items.into_iter().enumerate() {
+      self.evaluator.declare(item_symbol, item);
+      if let Some(index_symbol) = index_symbol {
+        self
+          .evaluator
+          .declare(index_symbol, ConstValue::Number(index as f64));
       }
-    };
 
-    let (to_insert, to_update) = self.store.get_prepared_data()?;
+      match self.evaluate_with_previews(body, &mut found_preview) {
+        Ok(value) => values.push(value),
+        Err(span) => return found_preview.then_some(Err(span)),
+      }
+    }
 
-    let tx = conn.transaction()?;
-    {
-      let mut insert_query = tx.prepare(
-        "INSERT INTO images (url, placeholder, preview_type, cache_key, original_width, original_height) VALUES (?, ?, ?, ?, ?, ?)",
-      )?;
-
-      if !to_insert.is_empty() {
-        create_log(
-          log::style_info(format!(
-            "Inserting {} new images into the database",
-            to_insert.len()
-          )),
-          LogLevel::Verbose,
-        );
+    found_preview.then_some(Ok(ConstValue::Array(values)))
+  }
+
+  /// Evaluates an expression in which preview calls may appear,
+  /// eg: the `{ ...p, ph: preview(p.src) }` callback body of a static map.
+  fn evaluate_with_previews(
+    &mut self,
+    expr: &Expression<'a>,
+    found_preview: &mut bool,
+  ) -> Result<ConstValue, Span> {
+    match expr {
+      Expression::CallExpression(call) => {
+        let Some(preset) = self.get_callee_preset(&call.callee) else {
+          return self.evaluator.evaluate(expr);
+        };
+        *found_preview = true;
+
+        let url = match call.arguments.first() {
+          Some(arg) => self.get_image_url_from_argument(arg)?,
+          None => return Err(call.span),
+        };
+        let options = self.get_preview_options_from_argument(&call.arguments.get(1), &preset);
+
+        if self.pass == Pass::First {
+          self.has_changes = true;
+          self
+            .images
+            .queue_image(url.clone(), options, preset.is_meta())
+            .map_err(|_| call.span)?;
+          return Ok(ConstValue::String(url));
+        }
+
+        self
+          .get_replacement_value(url, &options, &preset)
+          .map_err(|_| call.span)
+      }
+      Expression::ObjectExpression(object) => {
+        let mut properties = ConstObject::default();
+
+        for property in object.properties.iter() {
+          match property {
+            ObjectPropertyKind::SpreadProperty(spread) => {
+              match self.evaluate_with_previews(&spread.argument, found_preview)? {
+                ConstValue::Object(spread_properties) => properties.extend(spread_properties),
+                _ => return Err(spread.span),
+              }
+            }
+            ObjectPropertyKind::ObjectProperty(key_value) => {
+              if key_value.kind != PropertyKind::Init || key_value.method {
+                return Err(key_value.span);
+              }
+              let key = key_value.key.static_name().ok_or(key_value.key.span())?;
+              let value = self.evaluate_with_previews(&key_value.value, found_preview)?;
+              properties.insert(key.to_string(), value);
+            }
+          }
+        }
+
+        Ok(ConstValue::Object(properties))
+      }
+      Expression::ArrayExpression(array) => {
+        let mut items = vec![];
+        for element in array.elements.iter() {
+          match element.as_expression() {
+            Some(element) => items.push(self.evaluate_with_previews(element, found_preview)?),
+            None => return self.evaluator.evaluate(expr),
+          }
+        }
+        Ok(ConstValue::Array(items))
       }
+      Expression::ParenthesizedExpression(parenthesized) => {
+        self.evaluate_with_previews(&parenthesized.expression, found_preview)
+      }
+      _ => self.evaluator.evaluate(expr),
+    }
+  }
 
-      for (url, placeholder, preview_type, cache_key, original_width, original_height) in to_insert
+  /// Builds a literal expression (string, number, object, array...) from a value.
+  fn create_expression_from_value(&self, value: &ConstValue, span: Span) -> Expression<'a> {
+    match value {
+      ConstValue::String(string) => match self
+        .hoisted_placeholders
+        .iter()
+        .find(|(placeholder, _)| placeholder == string)
       {
-        insert_query.execute((
-          url,
-          placeholder,
-          preview_type,
-          cache_key,
-          original_width,
-          original_height,
-        ))?;
-      }
-
-      let mut update_query = tx.prepare(
-        "UPDATE images SET placeholder = ?, preview_type = ?, cache_key = ? WHERE id = ?",
-      )?;
-
-      if !to_update.is_empty() {
-        create_log(
-          log::style_info(format!(
-            "Updating {} existing images in the database",
-            to_update.len()
-          )),
-          LogLevel::Verbose,
+        // Eg: __noco_0 for a placeholder used more than once
+        Some((_, name)) => self
+          .ast_builder
+          .expression_identifier(span, self.ast_builder.atom(name)),
+        None => self.create_string_literal(string, span),
+      },
+      ConstValue::Number(number) if *number < 0.0 => self.ast_builder.expression_unary(
+        span,
+        UnaryOperator::UnaryNegation,
+        self
+          .ast_builder
+          .expression_numeric_literal(span, -number, None, NumberBase::Decimal),
+      ),
+      ConstValue::Number(number) => {
+        self
+          .ast_builder
+          .expression_numeric_literal(span, *number, None, NumberBase::Decimal)
+      }
+      ConstValue::Boolean(boolean) => self.ast_builder.expression_boolean_literal(span, *boolean),
+      ConstValue::Object(object) => {
+        let properties = self.ast_builder.vec_from_iter(
+          object
+            .iter()
+            .map(|(key, value)| self.create_object_property(key, value, span)),
         );
+        self.ast_builder.expression_object(span, properties)
       }
-
-      for (id, _, placeholder, preview_type, cache_key, original_width, original_height) in
-        to_update
-      {
-        update_query.execute((
-          placeholder,
-          preview_type,
-          cache_key,
-          id,
-          original_width,
-          original_height,
-        ))?;
+      ConstValue::Array(items) => {
+        let elements =
+          self.ast_builder.vec_from_iter(items.iter().map(|item| {
+            ArrayExpressionElement::from(self.create_expression_from_value(item, span))
+          }));
+        self.ast_builder.expression_array(span, elements)
       }
     }
+  }
 
-    tx.commit()?;
-    Ok(())
+  /// Builds a `key: value` object property.
+  fn create_object_property(
+    &self,
+    key: &str,
+    value: &ConstValue,
+    span: Span,
+  ) -> ObjectPropertyKind<'a> {
+    let key = if is_identifier_name(key) {
+      self
+        .ast_builder
+        .property_key_static_identifier(span, self.ast_builder.atom(key))
+    } else {
+      PropertyKey::from(self.create_string_literal(key, span))
+    };
+    self.ast_builder.object_property_kind_object_property(
+      span,
+      PropertyKind::Init,
+      key,
+      self.create_expression_from_value(value, span),
+      false,
+      false,
+      false,
+    )
   }
 
   /// Function called during the second pass of the transformation.
-  /// It retrieves the processed image URL from the store based on the function call.
+  /// It retrieves the value replacing the function call from the store:
+  /// the placeholder, or the metadata object for `previewMeta()` calls.
   fn get_image_result_from_fn_call(
     &mut self,
     call: &mut OxcBox<'a, CallExpression<'a>>,
-  ) -> Result<(String, PreviewOptions), Box<dyn std::error::Error + '_>> {
+    preset: &PreviewPreset,
+  ) -> Result<(ConstValue, PreviewOptions), Box<dyn std::error::Error + '_>> {
     if let Some(first_arg) = call.arguments.first() {
-      if let Expression::StringLiteral(string_value) = first_arg.as_expression().unwrap() {
-        let image_url = string_value.value.to_string();
-        let options = self.get_preview_options_from_argument(&call.arguments.get(1));
-        let placeholder_image_url = self
-          .store
-          .get_placeholder_from_url_and_options(image_url.clone(), &options)?;
+      if let Ok(image_url) = self.get_image_url_from_argument(first_arg) {
+        let options = self.get_preview_options_from_argument(&call.arguments.get(1), preset);
+        let value = self.get_replacement_value(image_url, &options, preset)?;
 
-        return Ok((placeholder_image_url.to_string(), options));
+        return Ok((value, options));
       }
     }
     create_log(
       log::style_error(format!(
-        "No image URL provided in the function call. File {}:{}",
-        self.file_path, call.span.start
+        "No image URL provided in the function call. File: {}",
+        self.get_location(call.span)
       )),
       LogLevel::Error,
     );
     Err("No image URL provided in the function call".into())
   }
 
+  /// Returns the value a call is replaced with, the placeholder or the metadata object.
+  fn get_replacement_value(
+    &self,
+    url: String,
+    options: &PreviewOptions,
+    preset: &PreviewPreset,
+  ) -> Result<ConstValue, Box<dyn std::error::Error + '_>> {
+    if !preset.is_meta() {
+      let placeholder = self
+        .images
+        .store
+        .get_placeholder_from_url_and_options(url, options)?;
+      return Ok(ConstValue::String(placeholder));
+    }
+
+    let meta = self
+      .images
+      .store
+      .get_meta_from_url_and_options(url, options)?;
+    let mut object = ConstObject::default();
+    object.insert(
+      "placeholder".to_string(),
+      ConstValue::String(meta.placeholder),
+    );
+    object.insert("width".to_string(), ConstValue::Number(meta.width as f64));
+    object.insert("height".to_string(), ConstValue::Number(meta.height as f64));
+    let aspect_ratio = meta.width as f64 / meta.height.max(1) as f64;
+    object.insert(
+      "aspectRatio".to_string(),
+      ConstValue::Number((aspect_ratio * 10000.0).round() / 10000.0),
+    );
+    object.insert(
+      "dominantColor".to_string(),
+      ConstValue::String(meta.dominant_color),
+    );
+    object.insert("format".to_string(), ConstValue::String(meta.format));
+    Ok(ConstValue::Object(object))
+  }
+
   /// Extracts the preview options from the function call arguments.
-  fn get_preview_options_from_argument(&self, arg: &Option<&Argument<'a>>) -> PreviewOptions {
+  /// The preset of the called function is applied on top of the global options.
+  /// Values are resolved through `const` bindings, spreads and string concatenation.
+  /// Properties that can't be resolved are reported and ignored.
+  fn get_preview_options_from_argument(
+    &self,
+    arg: &Option<&Argument<'a>>,
+    preset: &PreviewPreset,
+  ) -> PreviewOptions {
     let mut preview_options = PreviewOptions::from_global_options(&self.options);
-    if arg.is_none() {
+    preview_options.apply_preset(preset);
+
+    let Some(user_options_arg) = arg else {
       return preview_options;
-    }
-    let user_options_arg = arg.unwrap();
-    if let Expression::ObjectExpression(object_expr) = user_options_arg.as_expression().unwrap() {
-      object_expr.properties.iter().for_each(|prop| {
-        if let ObjectPropertyKind::ObjectProperty(key_value) = prop {
-          let key_str = match &key_value.key {
-            PropertyKey::StringLiteral(key) => key.value.as_str(),
-            PropertyKey::StaticIdentifier(key) => key.name.as_str(),
-            _ => return,
-          };
-
-          match key_str {
-            "width" => {
-              if let Expression::NumericLiteral(numeric_literal) = &key_value.value {
-                preview_options.width = Some(numeric_literal.value as u32);
-              }
-            }
-            "height" => {
-              if let Expression::NumericLiteral(numeric_literal) = &key_value.value {
-                preview_options.height = Some(numeric_literal.value as u32);
-              }
-            }
-            "placeholderType" => {
-              if let Expression::StringLiteral(string_literal) = &key_value.value {
-                preview_options.output_kind =
-                  PlaceholderImageOutputKind::from_string(&string_literal.value);
-              }
-            }
-            "replaceFunctionCall" => {
-              if let Expression::BooleanLiteral(boolean_literal) = &key_value.value {
-                preview_options.replace_function_call = boolean_literal.value;
-              }
-            }
-            "cache" => {
-              if let Expression::BooleanLiteral(boolean_literal) = &key_value.value {
-                preview_options.cache = boolean_literal.value;
-              }
-            }
-            "wrapWithSvg" => {
-              if let Expression::BooleanLiteral(boolean_literal) = &key_value.value {
-                preview_options.wrap_with_svg = boolean_literal.value;
-              }
-            }
-            _ => {}
-          }
+    };
+    let Some(user_options_expr) = user_options_arg.as_expression() else {
+      self.report_unresolved("the preview options", user_options_arg.span());
+      return preview_options;
+    };
+
+    let properties = match user_options_expr {
+      Expression::ObjectExpression(object_expr) => {
+        let (properties, unresolved) = self.evaluator.evaluate_object_properties(object_expr);
+        for span in unresolved {
+          self.report_unresolved("a preview option", span);
         }
-      });
+        properties
+      }
+      _ => match self.evaluator.evaluate(user_options_expr) {
+        Ok(ConstValue::Object(properties)) => properties,
+        Ok(_) => {
+          self.report_unresolved("the preview options", user_options_expr.span());
+          return preview_options;
+        }
+        Err(span) => {
+          self.report_unresolved("the preview options", span);
+          return preview_options;
+        }
+      },
+    };
+
+    for (key, value) in properties.iter() {
+      match (key.as_str(), value) {
+        ("width", ConstValue::Number(number)) => {
+          preview_options.width = Some(*number as u32);
+        }
+        ("height", ConstValue::Number(number)) => {
+          preview_options.height = Some(*number as u32);
+        }
+        ("placeholderType", ConstValue::String(string)) => {
+          preview_options.output_kind = PlaceholderImageOutputKind::from_string(string);
+        }
+        ("replaceFunctionCall", ConstValue::Boolean(boolean)) => {
+          preview_options.replace_function_call = *boolean;
+        }
+        ("cache", ConstValue::Boolean(boolean)) => {
+          preview_options.cache = *boolean;
+        }
+        ("wrapWithSvg", ConstValue::Boolean(boolean)) => {
+          preview_options.wrap_with_svg = *boolean;
+        }
+        ("trim", ConstValue::Boolean(boolean)) => {
+          preview_options.trim = *boolean;
+        }
+        ("trimTolerance", ConstValue::Number(number)) => {
+          preview_options.trim_tolerance = *number as u32;
+        }
+        ("targetArea", ConstValue::Number(number)) => {
+          preview_options.target_area = Some(*number as u32);
+        }
+        ("minSide", ConstValue::Number(number)) => {
+          preview_options.min_side = Some(*number as u32);
+        }
+        ("maxSide", ConstValue::Number(number)) => {
+          preview_options.max_side = Some(*number as u32);
+        }
+        ("maxBytes", ConstValue::Number(number)) => {
+          preview_options.max_bytes = Some(*number as u32);
+        }
+        ("targetSimilarity", ConstValue::Number(number)) => {
+          preview_options.target_similarity = Some(*number);
+        }
+        ("crop", ConstValue::Object(crop)) => {
+          preview_options.crop = Self::get_crop_rect_from_value(crop);
+        }
+        ("rotate", ConstValue::Number(number)) => {
+          preview_options.rotate = Some(*number as u32);
+        }
+        ("flip", ConstValue::String(string)) => {
+          preview_options.flip = FlipDirection::from_string(string);
+        }
+        _ => {}
+      }
     }
 
     preview_options
   }
 
-  /// Spawns a task to process the image asynchronously.
-  /// This function is called during the first pass of the transformation.
-  /// If the URL is an actual URL, it downloads the image and processes it.
-  /// If the URL is a relative path, it reads the image from the public directory.
-  /// The processed image output is then inserted or updated in the store.
-  fn spawn_image_resize(&mut self, url: String, options: PreviewOptions) {
-    let url_parse = Url::parse(&url);
-
-    if url_parse.is_err() {
-      // Assumes the URL is a relative path to an image in the public directory
-
-      let public_dir = self.options.public_dir.clone();
-      if let Some(public_dir) = public_dir {
-        let relative_url = url.strip_prefix("/").unwrap_or(&url);
-        let image_path = PathBuf::from(public_dir.clone()).join(relative_url);
-
-        if image_path.exists() {
-          let file_read = std::fs::read(image_path.as_path());
-          if file_read.is_err() {
-            create_log(
-              style_error(format!(
-                "Failed to read image from public directory: {:?} {:?}. File {}",
-                image_path, public_dir, self.file_path
-              )),
-              LogLevel::Error,
-            );
-            return;
+  /// Reads a `crop: { x, y, width, height, unit }` object from the preview options.
+  /// All four dimensions must be numbers, otherwise the crop is ignored.
+  fn get_crop_rect_from_value(crop: &ConstObject) -> Option<CropRect> {
+    let number = |key: &str| match crop.get(key) {
+      Some(ConstValue::Number(number)) => Some(*number),
+      _ => None,
+    };
+    let unit = match crop.get("unit") {
+      Some(ConstValue::String(unit)) => Some(CropUnit::from_string(unit)),
+      _ => None,
+    };
+
+    Some(CropRect {
+      x: number("x")?,
+      y: number("y")?,
+      width: number("width")?,
+      height: number("height")?,
+      unit,
+    })
+  }
+
+  /// Resolves the image URL passed as the first argument of a preview call.
+  /// Returns the span of the part that couldn't be resolved otherwise.
+  fn get_image_url_from_argument(&self, arg: &Argument<'a>) -> Result<String, Span> {
+    let expr = arg.as_expression().ok_or(arg.span())?;
+    self.get_image_url_from_expression(expr)
+  }
+
+  fn get_image_url_from_expression(&self, expr: &Expression<'a>) -> Result<String, Span> {
+    if let Some(url) = self.get_module_relative_url(expr) {
+      return url;
+    }
+
+    match self.evaluator.evaluate(expr)? {
+      ConstValue::String(url) => Ok(resolve_image_specifier(
+        &url,
+        &self.file_path,
+        self.options.alias.as_ref(),
+      )),
+      _ => Err(expr.span()),
+    }
+  }
+
+  /// Resolves `new URL('./image.jpg', import.meta.url)` relative to the transformed file.
+  /// Local images become the key of a local image, eg: `./src/assets/hero.jpg`, absolute URLs are kept as they are.
+  /// Returns None when the expression isn't a `new URL(..., import.meta.url)` expression.
+  fn get_module_relative_url(&self, expr: &Expression<'a>) -> Option<Result<String, Span>> {
+    let Expression::NewExpression(new_expr) = expr.without_parentheses() else {
+      return None;
+    };
+    let Expression::Identifier(callee) = &new_expr.callee else {
+      return None;
+    };
+    // A local binding named `URL` isn't the global URL constructor
+    if callee.name != "URL" || self.get_symbol_id(callee).is_some() {
+      return None;
+    }
+    let [specifier, base] = new_expr.arguments.as_slice() else {
+      return None;
+    };
+    match base.as_expression()?.without_parentheses() {
+      Expression::StaticMemberExpression(member)
+        if member.property.name == "url"
+          && matches!(&member.object, Expression::MetaProperty(meta) if meta.meta.name == "import") =>
+        {}
+      _ => return None,
+    }
+
+    let resolve = || -> Result<String, Span> {
+      let specifier_expr = specifier.as_expression().ok_or(specifier.span())?;
+      let ConstValue::String(specifier) = self.evaluator.evaluate(specifier_expr)? else {
+        return Err(specifier_expr.span());
+      };
+      if let Ok(url) = Url::parse(&specifier) {
+        return Ok(url.to_string());
+      }
+
+      let url =
+        resolve_module_relative(&specifier, &self.file_path).ok_or(specifier_expr.span())?;
+      Ok(get_local_image_key(&url))
+    };
+
+    Some(resolve())
+  }
+
+  /// Resolves the image URL of every branch of a conditional or logical expression,
+  /// eg: `cond ? 'a.jpg' : 'b.jpg'` or `src || 'fallback.jpg'`.
+  /// The operands of `&&` aren't branches, and other expressions have a single branch.
+  fn get_image_urls_from_expression(&self, expr: &Expression<'a>) -> Vec<Result<String, Span>> {
+    match expr {
+      Expression::ConditionalExpression(conditional) => {
+        let mut urls = self.get_image_urls_from_expression(&conditional.consequent);
+        urls.extend(self.get_image_urls_from_expression(&conditional.alternate));
+        urls
+      }
+      Expression::LogicalExpression(logical) if is_url_fallback(logical.operator) => {
+        let mut urls = self.get_image_urls_from_expression(&logical.left);
+        urls.extend(self.get_image_urls_from_expression(&logical.right));
+        urls
+      }
+      Expression::ParenthesizedExpression(parenthesized) => {
+        self.get_image_urls_from_expression(&parenthesized.expression)
+      }
+      _ => vec![self.get_image_url_from_expression(expr)],
+    }
+  }
+
+  /// Rewrites a call whose image URL is a conditional or logical expression, eg:
+  /// `preview(cond ? 'a.jpg' : 'b.jpg')` becomes `(cond ? '<placeholder a>' : '<placeholder b>')`.
+  /// Branches that can't be resolved keep calling the preview function at runtime.
+  /// Returns the expression replacing the whole call when `replace_function_call` is enabled.
+  /// Metadata objects always replace the whole call.
+  fn replace_url_branches_in_call(
+    &mut self,
+    call: &mut OxcBox<'a, CallExpression<'a>>,
+    preset: &PreviewPreset,
+  ) -> Option<Expression<'a>> {
+    let options = self.get_preview_options_from_argument(&call.arguments.get(1), preset);
+    let replace_function_call = options.replace_function_call || preset.is_meta();
+    let wrapper = replace_function_call.then(|| {
+      (
+        call.callee.clone_in_with_semantic_ids(self.allocator),
+        call
+          .arguments
+          .get(1)
+          .map(|arg| arg.clone_in_with_semantic_ids(self.allocator)),
+      )
+    });
+
+    let first_arg = call.arguments.first_mut()?.as_expression_mut()?;
+    self.replace_url_branch(first_arg, &options, preset, wrapper.as_ref());
+
+    if !replace_function_call {
+      self.record_patch(first_arg.span(), first_arg);
+      return None;
+    }
+
+    let branches = first_arg.take_in(self.allocator);
+    let replacement = self
+      .ast_builder
+      .expression_parenthesized(call.span, branches);
+    self.record_patch(call.span, &replacement);
+    Some(replacement)
+  }
+
+  /// Replaces the resolvable branches of an image URL expression with their placeholders.
+  /// When `wrapper` (the callee and options of the call) is given,
+  /// unresolvable branches are wrapped in a call to the preview function, and the unresolvable
+  /// left operand of `||` or `??` stays the test: `src || 'a.jpg'` becomes `src ? preview(src) : '<placeholder a>'`.
+  fn replace_url_branch(
+    &mut self,
+    expr: &mut Expression<'a>,
+    options: &PreviewOptions,
+    preset: &PreviewPreset,
+    wrapper: Option<&(Expression<'a>, Option<Argument<'a>>)>,
+  ) {
+    match expr {
+      Expression::ConditionalExpression(conditional) => {
+        self.replace_url_branch(&mut conditional.consequent, options, preset, wrapper);
+        self.replace_url_branch(&mut conditional.alternate, options, preset, wrapper);
+      }
+      Expression::LogicalExpression(logical) if is_url_fallback(logical.operator) => {
+        // A resolved left operand is a non-empty string, so the operator still picks it,
+        // and without a wrapper the call is kept and still tests the left operand
+        if wrapper.is_none() || self.get_image_url_from_expression(&logical.left).is_ok() {
+          self.replace_url_branch(&mut logical.left, options, preset, wrapper);
+          self.replace_url_branch(&mut logical.right, options, preset, wrapper);
+          return;
+        }
+        // The left operand would be evaluated twice, so the whole expression is kept at runtime
+        if !is_plain_reference(&logical.left) {
+          self.wrap_in_preview_call(expr, wrapper);
+          return;
+        }
+
+        let left = logical.left.take_in(self.allocator);
+        let mut consequent = left.clone_in_with_semantic_ids(self.allocator);
+        self.wrap_in_preview_call(&mut consequent, wrapper);
+        let mut alternate = logical.right.take_in(self.allocator);
+        self.replace_url_branch(&mut alternate, options, preset, wrapper);
+
+        let test = match logical.operator {
+          LogicalOperator::Or => left,
+          _ => {
+            let span = left.span();
+            self.ast_builder.expression_binary(
+              span,
+              left,
+              BinaryOperator::Inequality,
+              self.ast_builder.expression_null_literal(SPAN),
+            )
           }
-          let bytes = Bytes::from(file_read.unwrap());
-          let url_clone = url.clone();
-          let store = Arc::clone(&self.store);
-          let file_path_clone = self.file_path.clone();
-
-          self.tasks.push(spawn_task(async move {
-            match process_image(&bytes, &url_clone, &options).await {
-              Ok(out) => {
-                let _ = store.insert_or_update(
-                  url_clone,
-                  out.base64_str,
-                  out.original_width,
-                  out.original_height,
-                  &options,
-                );
-              }
-              Err(e) => create_log(
-                format!(
-                  "Failed to process image {} in {}. Error: {}",
-                  url_clone, file_path_clone, e
-                ),
-                LogLevel::Error,
-              ),
-            }
-          }));
+        };
+        *expr = self
+          .ast_builder
+          .expression_conditional(logical.span, test, consequent, alternate);
+      }
+      Expression::ParenthesizedExpression(parenthesized) => {
+        self.replace_url_branch(&mut parenthesized.expression, options, preset, wrapper);
+      }
+      _ => {
+        let value = self
+          .get_image_url_from_expression(expr)
+          .ok()
+          .and_then(|url| self.get_replacement_value(url, options, preset).ok());
+
+        if let Some(value) = value {
+          *expr = self.create_expression_from_value(&value, expr.span());
         } else {
-          create_log(
-            format!(
-              "Image not found in public directory: {:?}. Image used in file: {}",
-              image_path, self.file_path
-            ),
-            LogLevel::Error,
-          );
+          self.wrap_in_preview_call(expr, wrapper);
         }
-      } else {
-        create_log(
-          format!("Invalid public dir. Processing image at {}", self.file_path),
-          LogLevel::Error,
-        );
       }
+    }
+  }
+
+  /// Wraps an image URL expression in a call to the preview function of `wrapper`, when given.
+  fn wrap_in_preview_call(
+    &self,
+    expr: &mut Expression<'a>,
+    wrapper: Option<&(Expression<'a>, Option<Argument<'a>>)>,
+  ) {
+    let Some((callee, options_arg)) = wrapper else {
+      return;
+    };
+
+    let span = expr.span();
+    let mut arguments = self
+      .ast_builder
+      .vec1(Argument::from(expr.take_in(self.allocator)));
+    if let Some(options_arg) = options_arg {
+      arguments.push(options_arg.clone_in_with_semantic_ids(self.allocator));
+    }
+    *expr = self.ast_builder.expression_call(
+      span,
+      callee.clone_in_with_semantic_ids(self.allocator),
+      NONE,
+      arguments,
+      false,
+    );
+  }
+
+  fn create_string_literal(&self, value: &str, span: Span) -> Expression<'a> {
+    let atom = self.ast_builder.atom(self.allocator.alloc_str(value));
+    let lit = StringLiteral {
+      value: atom,
+      raw: None,
+      span,
+      lone_surrogates: false,
+    };
+    Expression::StringLiteral(OxcBox::new_in(lit, self.allocator))
+  }
+
+  /// Logs an argument that couldn't be resolved at build time.
+  /// Only reported during the first pass, so that every problem is logged once.
+  fn report_unresolved(&self, what: &str, span: Span) {
+    if self.pass != Pass::First {
+      return;
+    }
+
+    create_log(
+      log::style_error(format!(
+        "Could not statically resolve {}. Only literals, const bindings, defined values, template literals, string concatenation and object spreads are supported. File: {}",
+        what,
+        self.get_location(span)
+      )),
+      LogLevel::Error,
+    );
+  }
+
+  /// Returns the location of a span as `path:line:column`, both 1-based.
+  /// Spans of components are moved back to the component first.
+  fn get_location(&self, span: Span) -> String {
+    let offset = match self.component {
+      Some(module) => module.original_offset(span.start).unwrap_or(0),
+      None => span.start,
+    };
+    let before = &self.code[..(offset as usize).min(self.code.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    format!(
+      "{}:{}:{}",
+      self.file_path,
+      before.matches('\n').count() + 1,
+      before[line_start..].chars().count() + 1
+    )
+  }
+
+  /// Returns the preset if a callee refers to a preview function of one of the import sources,
+  /// either directly (`preview(...)`) or through a namespace binding (`noco.preview(...)`).
+  fn get_callee_preset(&self, callee: &Expression<'a>) -> Option<PreviewPreset> {
+    match callee {
+      Expression::Identifier(identifier) => {
+        let symbol_id = self.get_symbol_id(identifier)?;
+        self.util_import_symbols.get(&symbol_id).cloned()
+      }
+      Expression::StaticMemberExpression(member) => {
+        let Expression::Identifier(object) = &member.object else {
+          return None;
+        };
+        let symbol_id = self.get_symbol_id(object)?;
+        let source_index = self.util_namespace_symbols.get(&symbol_id)?;
+        self.import_sources[*source_index]
+          .names
+          .get(member.property.name.as_str())
+          .cloned()
+      }
+      _ => None,
+    }
+  }
+
+  fn get_import_source_index(&self, source: &str) -> Option<usize> {
+    self
+      .import_sources
+      .iter()
+      .position(|import_source| import_source.source == source)
+  }
+
+  /// Returns the preset of an exported function name of the import source at `source_index`.
+  fn get_preset(&self, source_index: usize, name: &str) -> Option<PreviewPreset> {
+    self.import_sources[source_index].names.get(name).cloned()
+  }
+
+  /// Whether the node at `span` follows a `@nocojs-ignore` comment, or is in a statement that does.
+  fn is_ignored(&self, span: Span) -> bool {
+    self.ignored_depth > 0 || self.ignored_positions.contains(&span.start)
+  }
+
+  fn get_symbol_id(&self, identifier: &IdentifierReference<'a>) -> Option<SymbolId> {
+    self
+      .scoping
+      .get_reference(identifier.reference_id())
+      .symbol_id()
+  }
+
+  /// Records the printed `replacement` for the source text at `span`.
+  /// Only used when `preserve_formatting` is enabled.
+  fn record_patch(&mut self, span: Span, replacement: &Expression<'a>) {
+    let Some(patcher) = &mut self.patcher else {
+      return;
+    };
+
+    let code = print_expression(replacement, self.single_quote);
+    // The parentheses of a sequence are only printed when it's part of a larger expression
+    let code = match replacement {
+      Expression::SequenceExpression(_) => format!("({})", code),
+      _ => code,
+    };
+    patcher.replace(span, code);
+  }
+
+  /// Prepends the asset reference of a call to its replacement, if any, eg:
+  /// `preview(new URL('./a.jpg', import.meta.url))` becomes
+  /// `(new URL('./a.jpg', import.meta.url), "data:...")`.
+  /// The value is still the replacement, and bundlers still see the asset.
+  fn keep_asset_reference(
+    &self,
+    asset_reference: Option<Expression<'a>>,
+    replacement: Expression<'a>,
+    span: Span,
+  ) -> Expression<'a> {
+    let Some(asset_reference) = asset_reference else {
+      return replacement;
+    };
+    let expressions = self
+      .ast_builder
+      .vec_from_array([asset_reference, replacement]);
+    self.ast_builder.expression_sequence(span, expressions)
+  }
+
+  /// Adds the placeholder, the intrinsic dimensions and the aspect ratio to a JSX image element,
+  /// eg: `<img src="/a.jpg" data-nocojs />` becomes
+  /// `<img src="/a.jpg" width={1600} height={900} data-placeholder={"data:..."} style={{ aspectRatio: "1600 / 900" }} />`.
+  /// Existing `width` and `height` attributes are kept, and existing style properties take precedence.
+  fn enrich_jsx_image(
+    &mut self,
+    element: &mut JSXOpeningElement<'a>,
+    jsx_images: &JsxImageOptions,
+  ) {
+    let is_component = jsx_images
+      .components
+      .iter()
+      .flatten()
+      .any(|name| *name == element.name.to_string());
+    let marker = get_jsx_attribute(element, jsx_images.marker_attribute()).map(|attr| attr.span);
+    if marker.is_none() && !is_component {
+      return;
+    }
+
+    let url = match get_jsx_attribute(element, "src") {
+      Some(src) => self.get_jsx_attribute_url(src),
+      None => Err(element.span),
+    };
+    let url = match url {
+      Ok(url) => url,
+      Err(span) => return self.report_unresolved("the image src", span),
+    };
+
+    let options = PreviewOptions::from_global_options(&self.options);
+    if self.pass == Pass::First {
+      self.has_changes = true;
+      let _ = self.images.queue_image(url, options, true);
+      return;
+    }
+
+    let Ok(meta) = self
+      .images
+      .store
+      .get_meta_from_url_and_options(url, &options)
+    else {
+      return;
+    };
+
+    for (name, value) in [("width", meta.width), ("height", meta.height)] {
+      if get_jsx_attribute(element, name).is_none() {
+        let value = self.create_expression_from_value(&ConstValue::Number(value as f64), SPAN);
+        self.set_jsx_attribute(element, name, value);
+      }
+    }
+
+    let mut style = ConstObject::default();
+    style.insert(
+      "aspectRatio".to_string(),
+      ConstValue::String(format!("{} / {}", meta.width, meta.height)),
+    );
+    if jsx_images.placeholder_attribute() == "style" {
+      style.insert(
+        "backgroundImage".to_string(),
+        ConstValue::String(format!("url(\"{}\")", meta.placeholder)),
+      );
+      style.insert(
+        "backgroundSize".to_string(),
+        ConstValue::String("cover".to_string()),
+      );
     } else {
-      let client = &*HTTP_CLIENT;
-      let url_clone = url.clone();
-      let store = Arc::clone(&self.store);
-      let file_path_clone = self.file_path.clone();
-
-      self.tasks.push(spawn_task(async move {
-        match download_and_process_image(client, &url, &options).await {
-          Ok(image) => {
-            let _ = store.insert_or_update(
-              url_clone,
-              image.base64_str,
-              image.original_width,
-              image.original_height,
-              &options,
+      let placeholder =
+        self.create_expression_from_value(&ConstValue::String(meta.placeholder), SPAN);
+      self.set_jsx_attribute(element, jsx_images.placeholder_attribute(), placeholder);
+    }
+    self.merge_jsx_style(element, &style);
+
+    // The marker is a build-time directive only
+    if let Some(marker) = marker {
+      // Along with the whitespace separating it from the previous attribute
+      let start = element
+        .attributes
+        .iter()
+        .map(|attr| attr.span())
+        .filter(|span| !span.is_unspanned() && span.end <= marker.start)
+        .map(|span| span.end)
+        .max()
+        .unwrap_or(element.name.span().end);
+      element
+        .attributes
+        .retain(|attr| !matches!(attr, JSXAttributeItem::Attribute(attr) if attr.span == marker));
+      if let Some(patcher) = &mut self.patcher {
+        patcher.remove(Span::new(start, marker.end));
+      }
+    }
+  }
+
+  /// Injects `blurDataURL` into `next/image` elements using `placeholder="blur"`, eg:
+  /// `<Image src="https://cdn.example.com/a.jpg" placeholder="blur" />` becomes
+  /// `<Image src="https://cdn.example.com/a.jpg" placeholder="blur" blurDataURL={"data:..."} width={1600} height={900} />`.
+  /// Next.js only generates it for statically imported images, so only remote and public URLs are handled.
+  /// `width` and `height` are added when missing, unless the image uses `fill`.
+  fn inject_next_image_blur(&mut self, element: &mut JSXOpeningElement<'a>) {
+    let JSXElementName::IdentifierReference(name) = &element.name else {
+      return;
+    };
+    if !self
+      .get_symbol_id(name)
+      .is_some_and(|symbol_id| self.next_image_symbols.contains(&symbol_id))
+    {
+      return;
+    }
+
+    let is_blur = get_jsx_attribute(element, "placeholder").is_some_and(|attr| {
+      matches!(&attr.value, Some(JSXAttributeValue::StringLiteral(value)) if value.value == "blur")
+    });
+    if !is_blur || get_jsx_attribute(element, "blurDataURL").is_some() {
+      return;
+    }
+
+    // Eg: src={heroImage} for a static import, which Next.js handles itself
+    let Some(Ok(url)) =
+      get_jsx_attribute(element, "src").map(|src| self.get_jsx_attribute_url(src))
+    else {
+      return;
+    };
+    let is_remote = Url::parse(&url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+    if !is_remote && !url.starts_with('/') {
+      return;
+    }
+
+    // Next.js blurs the image itself, so a plain image is used
+    let options = PreviewOptions {
+      output_kind: PlaceholderImageOutputKind::Normal,
+      wrap_with_svg: false,
+      ..PreviewOptions::from_global_options(&self.options)
+    };
+    if self.pass == Pass::First {
+      self.has_changes = true;
+      let _ = self.images.queue_image(url, options, true);
+      return;
+    }
+
+    let Ok(meta) = self
+      .images
+      .store
+      .get_meta_from_url_and_options(url, &options)
+    else {
+      return;
+    };
+
+    let blur_data_url =
+      self.create_expression_from_value(&ConstValue::String(meta.placeholder), SPAN);
+    self.set_jsx_attribute(element, "blurDataURL", blur_data_url);
+
+    if get_jsx_attribute(element, "fill").is_none() {
+      for (name, value) in [("width", meta.width), ("height", meta.height)] {
+        if get_jsx_attribute(element, name).is_none() {
+          let value = self.create_expression_from_value(&ConstValue::Number(value as f64), SPAN);
+          self.set_jsx_attribute(element, name, value);
+        }
+      }
+    }
+  }
+
+  /// Resolves the image URL of a `src` attribute, eg: `src="/a.jpg"` or `src={BASE + "/a.jpg"}`.
+  fn get_jsx_attribute_url(&self, attr: &JSXAttribute<'a>) -> Result<String, Span> {
+    match &attr.value {
+      Some(JSXAttributeValue::StringLiteral(literal)) => Ok(resolve_image_specifier(
+        &literal.value,
+        &self.file_path,
+        self.options.alias.as_ref(),
+      )),
+      Some(JSXAttributeValue::ExpressionContainer(container)) => {
+        let expr = container.expression.as_expression().ok_or(container.span)?;
+        self.get_image_url_from_expression(expr)
+      }
+      _ => Err(attr.span),
+    }
+  }
+
+  /// Adds the properties of `style` in front of the `style` attribute of an element.
+  /// The properties already set by the element win: they're skipped, or override them when spread.
+  /// String styles are left untouched.
+  fn merge_jsx_style(&mut self, element: &mut JSXOpeningElement<'a>, style: &ConstObject) {
+    let mut properties = self.ast_builder.vec();
+    let mut existing = self.ast_builder.vec();
+
+    match get_jsx_attribute(element, "style").map(|attr| &attr.value) {
+      None => {}
+      Some(Some(JSXAttributeValue::ExpressionContainer(container))) => {
+        match container.expression.as_expression() {
+          Some(Expression::ObjectExpression(object)) => {
+            existing = object.properties.clone_in_with_semantic_ids(self.allocator);
+          }
+          Some(expr) => {
+            let spread = expr.clone_in_with_semantic_ids(self.allocator);
+            existing.push(
+              self
+                .ast_builder
+                .object_property_kind_spread_property(SPAN, spread),
             );
           }
-          Err(e) => create_log(
-            format!(
-              "Failed to process image {} in {}. Error: {}",
-              url_clone, file_path_clone, e
-            ),
-            LogLevel::Error,
-          ),
+          None => return,
         }
-      }));
+      }
+      Some(_) => return,
     }
+
+    for (key, value) in style.iter() {
+      let is_defined = existing.iter().any(|property| match property {
+        ObjectPropertyKind::ObjectProperty(property) => property
+          .key
+          .static_name()
+          .is_some_and(|name| name == key.as_str()),
+        ObjectPropertyKind::SpreadProperty(_) => false,
+      });
+      if !is_defined {
+        properties.push(self.create_object_property(key, value, SPAN));
+      }
+    }
+    properties.extend(existing);
+
+    let object = self.ast_builder.expression_object(SPAN, properties);
+    self.set_jsx_attribute(element, "style", object);
+  }
+
+  /// Sets `name={value}` on a JSX element, replacing the attribute if it already exists.
+  /// New attributes are added after the attributes of the source, in the order they're set.
+  fn set_jsx_attribute(
+    &mut self,
+    element: &mut JSXOpeningElement<'a>,
+    name: &str,
+    value: Expression<'a>,
+  ) {
+    let patch = self.patcher.is_some().then(|| {
+      format!(
+        "{}={{{}}}",
+        name,
+        print_expression(&value, self.single_quote)
+      )
+    });
+
+    let value = self
+      .ast_builder
+      .jsx_attribute_value_expression_container(SPAN, JSXExpression::from(value));
+
+    let existing = element.attributes.iter_mut().find_map(|attr| match attr {
+      JSXAttributeItem::Attribute(attr) if attr.name.get_identifier().name == name => Some(attr),
+      _ => None,
+    });
+
+    if let Some(existing) = existing {
+      existing.value = Some(value);
+      // Attributes added by an earlier call have no source text to replace
+      if let (Some(patcher), Some(patch)) = (&mut self.patcher, patch) {
+        if !existing.span.is_unspanned() {
+          patcher.replace(existing.span, patch);
+        }
+      }
+      return;
+    }
+
+    let end = element
+      .attributes
+      .iter()
+      .map(|attr| attr.span())
+      .rfind(|span| !span.is_unspanned())
+      .map_or(element.name.span().end, |span| span.end);
+    if let (Some(patcher), Some(patch)) = (&mut self.patcher, patch) {
+      patcher.insert(end, format!(" {}", patch));
+    }
+
+    let name = self
+      .ast_builder
+      .jsx_attribute_name_identifier(SPAN, self.ast_builder.atom(name));
+    element.attributes.push(
+      self
+        .ast_builder
+        .jsx_attribute_item_attribute(SPAN, name, Some(value)),
+    );
   }
 }
 
 impl<'a> VisitMut<'a> for TransformVisitor<'a> {
-  /// Find all import declarations that import the "preview" function from "@nocojs/client".
+  /// Tracks the statements following a `@nocojs-ignore` comment, eg:
+  /// // @nocojs-ignore
+  /// const img = preview('/a.jpg');
+  fn visit_statement(&mut self, it: &mut Statement<'a>) {
+    let is_ignored = self.ignored_positions.contains(&it.span().start);
+    if is_ignored {
+      self.ignored_depth += 1;
+    }
+    walk_mut::walk_statement(self, it);
+    if is_ignored {
+      self.ignored_depth -= 1;
+    }
+  }
+
+  /// Enriches JSX image elements when `jsx_images` is enabled.
+  /// Children are visited first, so that preview calls inside the attributes are replaced
+  /// before the attributes are printed.
+  fn visit_jsx_opening_element(&mut self, it: &mut JSXOpeningElement<'a>) {
+    walk_mut::walk_jsx_opening_element(self, it);
+
+    if self.is_ignored(it.span) {
+      return;
+    }
+
+    if let Some(jsx_images) = self.options.jsx_images.clone() {
+      self.enrich_jsx_image(it, &jsx_images);
+    }
+    self.inject_next_image_blur(it);
+  }
+
+  /// Find all import declarations that import a preview function from one of the import sources.
   /// It identifies the import specifiers and stores their symbol IDs tp compare against preview function calls.
   fn visit_import_declaration(&mut self, it: &mut ImportDeclaration<'a>) {
-    if it.source.value == IMPORT_PATH {
-      it.specifiers
-        .as_ref()
-        .unwrap()
-        .iter()
-        .for_each(|specifier| {
-          if let ImportDeclarationSpecifier::ImportSpecifier(import_specifier) = specifier {
-            // Handle renamed imports
-            // Eg: import { preview as previewFn } from '@nocojs/client';
-            if let ModuleExportName::IdentifierName(identifier_name) = &import_specifier.imported {
-              if identifier_name.name == "preview" && self.pass == Pass::First {
-                self
-                  .util_import_symbols
-                  .push(import_specifier.local.symbol_id());
-              }
-            } else if import_specifier.local.name == "preview" {
+    // Eg: import Image from 'next/image';
+    if self.pass == Pass::First
+      && self.options.next_image_blur.unwrap_or(false)
+      && NEXT_IMAGE_SOURCES.contains(&it.source.value.as_str())
+    {
+      for specifier in it.specifiers.iter().flatten() {
+        if let ImportDeclarationSpecifier::ImportDefaultSpecifier(default_specifier) = specifier {
+          self
+            .next_image_symbols
+            .push(default_specifier.local.symbol_id());
+        }
+      }
+    }
+
+    let source_index = self.get_import_source_index(&it.source.value);
+
+    if let (Some(source_index), Some(specifiers), Pass::First) =
+      (source_index, &it.specifiers, &self.pass)
+    {
+      for specifier in specifiers.iter() {
+        match specifier {
+          // Handles renamed imports as well
+          // Eg: import { preview as previewFn } from '@nocojs/client';
+          ImportDeclarationSpecifier::ImportSpecifier(import_specifier) => {
+            if let Some(preset) = self.get_preset(source_index, &import_specifier.imported.name()) {
               self
                 .util_import_symbols
-                .push(import_specifier.local.symbol_id());
+                .insert(import_specifier.local.symbol_id(), preset);
             }
           }
-        });
+          // Eg: import * as noco from '@nocojs/client';
+          ImportDeclarationSpecifier::ImportNamespaceSpecifier(namespace_specifier) => {
+            self
+              .util_namespace_symbols
+              .insert(namespace_specifier.local.symbol_id(), source_index);
+          }
+          // The client has no default export
+          ImportDeclarationSpecifier::ImportDefaultSpecifier(_) => {}
+        }
+      }
     }
     walk_mut::walk_import_declaration(self, it);
   }
 
+  /// Find CommonJS requires and dynamic imports of the import sources.
+  /// Eg: const { preview } = require('@nocojs/client');
+  ///     const noco = require('@nocojs/client');
+  ///     const { preview: previewFn } = await import('@nocojs/client');
+  ///     const preview = require('@nocojs/client').preview;
+  fn visit_variable_declarator(&mut self, it: &mut VariableDeclarator<'a>) {
+    // Record const bindings so they can be used to resolve preview arguments.
+    if let (Pass::First, VariableDeclarationKind::Const, Some(init)) =
+      (&self.pass, it.kind, &it.init)
+    {
+      if let BindingPatternKind::BindingIdentifier(identifier) = &it.id.kind {
+        if let Ok(value) = self.evaluator.evaluate(init) {
+          self.evaluator.declare(identifier.symbol_id(), value);
+        }
+      }
+    }
+
+    if self.pass == Pass::First {
+      if let Some(init) = &it.init {
+        let init = init.without_parentheses();
+
+        if let Some(source_index) =
+          get_module_expression_source(init).and_then(|source| self.get_import_source_index(source))
+        {
+          match &it.id.kind {
+            BindingPatternKind::BindingIdentifier(identifier) => {
+              self
+                .util_namespace_symbols
+                .insert(identifier.symbol_id(), source_index);
+            }
+            BindingPatternKind::ObjectPattern(object_pattern) => {
+              for property in object_pattern.properties.iter() {
+                let Some(name) = property.key.static_name() else {
+                  continue;
+                };
+                let Some(preset) = self.get_preset(source_index, &name) else {
+                  continue;
+                };
+                if let Some(identifier) = property.value.get_binding_identifier() {
+                  self
+                    .util_import_symbols
+                    .insert(identifier.symbol_id(), preset);
+                }
+              }
+            }
+            _ => {}
+          }
+        } else if let Expression::StaticMemberExpression(member) = init {
+          let preset = get_module_expression_source(&member.object)
+            .and_then(|source| self.get_import_source_index(source))
+            .and_then(|source_index| self.get_preset(source_index, &member.property.name));
+
+          if let (Some(preset), Some(identifier)) = (preset, it.id.get_binding_identifier()) {
+            self
+              .util_import_symbols
+              .insert(identifier.symbol_id(), preset);
+          }
+        }
+      }
+    }
+    walk_mut::walk_variable_declarator(self, it);
+  }
+
   /// Handle function calls.
   /// Though visit_call_expression can be used, I don't think its possible to remove the function call
   /// and replace it with a string literal from within that function.
@@ -791,22 +2181,38 @@ impl<'a> VisitMut<'a> for TransformVisitor<'a> {
       return walk_mut::walk_expression(self, expr);
     };
 
-    let Expression::Identifier(identifier_calle) = &call.callee else {
+    // Eg: /* @nocojs-ignore */ preview('/a.jpg')
+    if self.is_ignored(call.span) {
       return walk_mut::walk_expression(self, expr);
-    };
+    }
 
-    let callee_ref = self.scoping.get_reference(identifier_calle.reference_id());
-    let Some(callee_symbol_id) = callee_ref.symbol_id() else {
-      return walk_mut::walk_expression(self, expr);
-    };
+    // Eg: GALLERY.map(src => preview(src))
+    let call_span = call.span;
+    if let Some(result) = self.evaluate_static_map(call) {
+      match result {
+        Ok(value) => {
+          if self.pass == Pass::Second {
+            let replacement = self.create_expression_from_value(&value, call_span);
+            self.record_patch(call_span, &replacement);
+            *expr = replacement;
+          }
+          // The preview calls of the callback were handled while evaluating it
+          return;
+        }
+        Err(span) => {
+          self.report_unresolved("an item of the mapped array", span);
+          return walk_mut::walk_expression(self, expr);
+        }
+      }
+    }
 
-    // Check if the function was imported from "@nocojs/client"
-    if !self.util_import_symbols.contains(&callee_symbol_id) {
+    // Check if the function was imported from one of the import sources
+    let Some(preset) = self.get_callee_preset(&call.callee) else {
       return walk_mut::walk_expression(self, expr);
-    }
+    };
 
     if self.pass == Pass::First {
-      let _ = self.prepare_image_from_fn_call(call);
+      let _ = self.prepare_image_from_fn_call(call, &preset);
       return walk_mut::walk_expression(self, expr);
     }
 
@@ -814,8 +2220,30 @@ impl<'a> VisitMut<'a> for TransformVisitor<'a> {
     // Second pass logic
     // ----
 
+    let is_branching = call
+      .arguments
+      .first()
+      .and_then(|arg| arg.as_expression())
+      .is_some_and(|arg| match arg.without_parentheses() {
+        Expression::ConditionalExpression(_) => true,
+        Expression::LogicalExpression(logical) => is_url_fallback(logical.operator),
+        _ => false,
+      });
+
+    if is_branching {
+      // Walk first, so that the preview calls wrapping unresolvable branches aren't visited
+      walk_mut::walk_expression(self, expr);
+
+      if let Expression::CallExpression(call) = expr {
+        if let Some(replacement) = self.replace_url_branches_in_call(call, &preset) {
+          *expr = replacement;
+        }
+      }
+      return;
+    }
+
     // If the image was valid, it should have been processed in the first pass and stored in the store.
-    let Ok((url, options)) = self.get_image_result_from_fn_call(call) else {
+    let Ok((value, options)) = self.get_image_result_from_fn_call(call, &preset) else {
       create_log(
         format!(
           "Failed to get image result from function call for {}",
@@ -826,26 +2254,32 @@ impl<'a> VisitMut<'a> for TransformVisitor<'a> {
       return walk_mut::walk_expression(self, expr);
     };
 
-    if options.replace_function_call {
-      // Replace entire function call with string literal
-      let atom = self.ast_builder.atom(self.allocator.alloc_str(&url));
-      let lit = StringLiteral {
-        value: atom,
-        raw: None,
-        span: call.span,
-        lone_surrogates: false,
-      };
-      *expr = Expression::StringLiteral(OxcBox::new_in(lit, self.allocator));
+    // Eg: `new URL('./a.jpg', import.meta.url)`, kept so that the bundler still emits the image
+    let asset_reference = call
+      .arguments
+      .first()
+      .and_then(|arg| arg.as_expression())
+      .filter(|arg| matches!(self.get_module_relative_url(arg), Some(Ok(_))))
+      .map(|arg| arg.clone_in_with_semantic_ids(self.allocator));
+
+    if options.replace_function_call || preset.is_meta() {
+      // Replace entire function call with the placeholder literal or the metadata object
+      let replacement = self.create_expression_from_value(&value, call.span);
+      let replacement = self.keep_asset_reference(asset_reference, replacement, call.span);
+      self.record_patch(call.span, &replacement);
+      *expr = replacement;
     } else {
       // Replace only the first argument
       let Some(first_arg) = call.arguments.first_mut().unwrap().as_expression_mut() else {
         return walk_mut::walk_expression(self, expr);
       };
 
-      if let Expression::StringLiteral(string_value) = first_arg {
-        let atom = self.ast_builder.atom(self.allocator.alloc_str(&url));
-        string_value.value = atom;
-      }
+      // The argument may be a constant expression, so it is replaced with a literal
+      let span = first_arg.span();
+      let replacement = self.create_expression_from_value(&value, span);
+      *first_arg = self.keep_asset_reference(asset_reference, replacement, span);
+
+      self.record_patch(span, first_arg);
     }
 
     walk_mut::walk_expression(self, expr);
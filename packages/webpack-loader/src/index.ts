@@ -42,23 +42,15 @@ export default async function nocoLoader(
     ? path.resolve(context, options.cacheFileDir)
     : path.join(context, ".nocojs");
 
-  let fileName = this.resourcePath;
-  let sourcemapFilePath = fileName;
-
-  if (fileName.endsWith(".vue") || fileName.endsWith(".svelte")) {
-    const fileNameExtension = fileName.split(".").pop()!;
-    sourcemapFilePath =
-      fileName.slice(0, -(fileNameExtension.length + 1)) +
-      `.${fileNameExtension}`;
-    fileName = `${fileName}.ts`;
-  }
+  // Vue, Svelte and Astro components are handled by @nocojs/core
+  const fileName = this.resourcePath;
 
   const transformOptions: TransformOptions = {
     ...options,
     publicDir,
     cacheFileDir,
     logLevel: options.logLevel || "info",
-    sourcemapFilePath,
+    sourcemapFilePath: fileName,
   };
 
   try {